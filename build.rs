@@ -0,0 +1,37 @@
+use std::fs;
+
+/// crates whose resolved version is exposed through `adt version-info`
+const TRACKED_CRATES: [&str; 5] = [
+    "arrow",
+    "datafusion",
+    "deltalake",
+    "deltalake-core",
+    "object_store",
+];
+
+fn main() {
+    println!("cargo:rerun-if-changed=Cargo.lock");
+    let lock = fs::read_to_string("Cargo.lock").unwrap_or_default();
+    for krate in TRACKED_CRATES {
+        let version = locked_version(&lock, krate).unwrap_or_else(|| "unknown".to_string());
+        println!(
+            "cargo:rustc-env=ADT_DEP_VERSION_{}={}",
+            krate.to_uppercase().replace('-', "_"),
+            version
+        );
+    }
+}
+
+fn locked_version(lock: &str, krate: &str) -> Option<String> {
+    let name_line = format!("name = \"{}\"", krate);
+    let mut lines = lock.lines();
+    while let Some(line) = lines.next() {
+        if line == name_line {
+            return lines
+                .next()
+                .and_then(|l| l.strip_prefix("version = \""))
+                .map(|v| v.trim_end_matches('"').to_string());
+        }
+    }
+    None
+}
@@ -0,0 +1,295 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+use log::debug;
+use object_store::local::LocalFileSystem;
+use object_store::path::Path;
+use object_store::{
+    GetOptions, GetResult, ListResult, MultipartUpload, ObjectMeta, ObjectStore, PutMultipartOpts,
+    PutOptions, PutPayload, PutResult, Result,
+};
+
+/// Read-through object store keeping a local copy of remote parquet files.
+///
+/// Only parquet data files are cached: they are immutable once written, whereas
+/// delta log files and listings must always reflect the remote state.
+/// When the cache grows over `max_size` bytes, the least recently used files are evicted.
+#[derive(Debug)]
+pub struct CachedObjectStore {
+    inner: Arc<dyn ObjectStore>,
+    local: LocalFileSystem,
+    cache_dir: PathBuf,
+    max_size: usize,
+    index: Mutex<CacheIndex>,
+}
+
+/// In memory view of the cached files, sparing a cache dir listing on each miss
+#[derive(Debug, Default)]
+struct CacheIndex {
+    /// size and last access tick of each cached file
+    entries: HashMap<Path, (usize, u64)>,
+    /// cached files by last access tick, least recently used first
+    lru: BTreeMap<u64, Path>,
+    total: usize,
+    tick: u64,
+}
+
+impl CacheIndex {
+    /// Index the files already in `dir`, from the least recently modified
+    fn load(dir: &std::path::Path) -> Self {
+        let mut files = vec![];
+        let mut dirs = vec![dir.to_path_buf()];
+        while let Some(current) = dirs.pop() {
+            let Ok(entries) = fs::read_dir(&current) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let Ok(meta) = entry.metadata() else {
+                    continue;
+                };
+                if meta.is_dir() {
+                    dirs.push(entry.path());
+                } else if let Ok(relative) = entry.path().strip_prefix(dir) {
+                    let location = Path::from_iter(
+                        relative
+                            .components()
+                            .map(|c| c.as_os_str().to_string_lossy().into_owned()),
+                    );
+                    files.push((meta.modified().ok(), location, meta.len() as usize));
+                }
+            }
+        }
+        files.sort_by_key(|(modified, _, _)| *modified);
+        let mut index = Self::default();
+        for (_, location, size) in files {
+            if CachedObjectStore::is_cacheable(&location) {
+                index.insert(location, size);
+            }
+        }
+        index
+    }
+
+    /// Mark `location` as used, returns whether it is cached
+    fn touch(&mut self, location: &Path) -> bool {
+        let Some((_, tick)) = self.entries.get_mut(location) else {
+            return false;
+        };
+        self.lru.remove(tick);
+        self.tick += 1;
+        *tick = self.tick;
+        self.lru.insert(self.tick, location.clone());
+        true
+    }
+
+    fn insert(&mut self, location: Path, size: usize) {
+        self.remove(&location);
+        self.tick += 1;
+        self.entries.insert(location.clone(), (size, self.tick));
+        self.lru.insert(self.tick, location);
+        self.total += size;
+    }
+
+    fn remove(&mut self, location: &Path) {
+        if let Some((size, tick)) = self.entries.remove(location) {
+            self.lru.remove(&tick);
+            self.total -= size;
+        }
+    }
+
+    /// Drop the least recently used files until the cache fits `max_size`, returns them
+    fn evict(&mut self, max_size: usize) -> Vec<Path> {
+        let mut evicted = vec![];
+        while self.total > max_size {
+            let Some((_, location)) = self.lru.pop_first() else {
+                break;
+            };
+            if let Some((size, _)) = self.entries.remove(&location) {
+                self.total -= size;
+            }
+            evicted.push(location);
+        }
+        evicted
+    }
+}
+
+impl CachedObjectStore {
+    pub fn try_new(
+        inner: Arc<dyn ObjectStore>,
+        cache_dir: PathBuf,
+        max_size: usize,
+    ) -> Result<Self> {
+        std::fs::create_dir_all(&cache_dir).map_err(|e| object_store::Error::Generic {
+            store: "CachedObjectStore",
+            source: Box::new(e),
+        })?;
+        Ok(Self {
+            inner,
+            local: LocalFileSystem::new_with_prefix(&cache_dir)?,
+            index: Mutex::new(CacheIndex::load(&cache_dir)),
+            cache_dir,
+            max_size,
+        })
+    }
+
+    fn is_cacheable(location: &Path) -> bool {
+        location.as_ref().ends_with(".parquet")
+    }
+
+    fn index(&self) -> std::sync::MutexGuard<'_, CacheIndex> {
+        // the index stays consistent even if a holder panicked
+        self.index.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Copy `location` into the cache, `false` when it is larger than the whole cache
+    async fn fetch(&self, location: &Path) -> Result<bool> {
+        debug!("cache miss for {}", location);
+        let remote = self.inner.get(location).await?;
+        let size = remote.meta.size;
+        if size > self.max_size {
+            // caching it would evict everything, itself included
+            debug!("{} is larger than the cache, read it remotely", location);
+            return Ok(false);
+        }
+        let bytes = remote.bytes().await?;
+        self.local.put(location, bytes.into()).await?;
+        // pick the evicted files under the lock, the file just fetched being the most recent
+        let evicted = {
+            let mut index = self.index();
+            index.insert(location.clone(), size);
+            index.evict(self.max_size)
+        };
+        for location in evicted {
+            debug!("evict {} from cache", location);
+            // readers of an evicted file fetch it again
+            match self.local.delete(&location).await {
+                Ok(()) | Err(object_store::Error::NotFound { .. }) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl fmt::Display for CachedObjectStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "CachedObjectStore({}, {})",
+            self.inner,
+            self.cache_dir.display()
+        )
+    }
+}
+
+#[async_trait]
+impl ObjectStore for CachedObjectStore {
+    async fn put_opts(
+        &self,
+        location: &Path,
+        payload: PutPayload,
+        opts: PutOptions,
+    ) -> Result<PutResult> {
+        self.inner.put_opts(location, payload, opts).await
+    }
+
+    async fn put_multipart_opts(
+        &self,
+        location: &Path,
+        opts: PutMultipartOpts,
+    ) -> Result<Box<dyn MultipartUpload>> {
+        self.inner.put_multipart_opts(location, opts).await
+    }
+
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
+        if !Self::is_cacheable(location) {
+            return self.inner.get_opts(location, options).await;
+        }
+        let cached = self.index().touch(location);
+        if !cached && !self.fetch(location).await? {
+            return self.inner.get_opts(location, options).await;
+        }
+        // conditional options refer to the remote object version, only keep the range
+        let local_options = GetOptions {
+            range: options.range.clone(),
+            head: options.head,
+            ..Default::default()
+        };
+        match self.local.get_opts(location, local_options.clone()).await {
+            // evicted, or removed from the cache dir, since it was indexed
+            Err(object_store::Error::NotFound { .. }) => {
+                self.index().remove(location);
+                if !self.fetch(location).await? {
+                    return self.inner.get_opts(location, options).await;
+                }
+                self.local.get_opts(location, local_options).await
+            }
+            result => result,
+        }
+    }
+
+    async fn delete(&self, location: &Path) -> Result<()> {
+        self.inner.delete(location).await
+    }
+
+    fn list(&self, prefix: Option<&Path>) -> BoxStream<'_, Result<ObjectMeta>> {
+        self.inner.list(prefix)
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+        self.inner.list_with_delimiter(prefix).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.copy(from, to).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.copy_if_not_exists(from, to).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+
+    async fn read(store: &CachedObjectStore, name: &str) -> usize {
+        let bytes = store.get(&Path::from(name)).await.unwrap().bytes().await;
+        bytes.unwrap().len()
+    }
+
+    #[tokio::test]
+    async fn hit_eviction_and_oversize() {
+        let inner = Arc::new(InMemory::new());
+        for (name, size) in [("a.parquet", 10), ("b.parquet", 10), ("big.parquet", 100)] {
+            inner
+                .put(&Path::from(name), vec![0u8; size].into())
+                .await
+                .unwrap();
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let store =
+            CachedObjectStore::try_new(inner.clone(), dir.path().to_path_buf(), 15).unwrap();
+
+        // cached on the first read, served locally once gone from the remote store
+        assert_eq!(read(&store, "a.parquet").await, 10);
+        assert!(dir.path().join("a.parquet").exists());
+        inner.delete(&Path::from("a.parquet")).await.unwrap();
+        assert_eq!(read(&store, "a.parquet").await, 10);
+
+        // least recently used file evicted to fit the new one
+        assert_eq!(read(&store, "b.parquet").await, 10);
+        assert!(!dir.path().join("a.parquet").exists());
+        assert!(dir.path().join("b.parquet").exists());
+
+        // larger than the whole cache, read remotely without evicting anything
+        assert_eq!(read(&store, "big.parquet").await, 100);
+        assert!(!dir.path().join("big.parquet").exists());
+        assert!(dir.path().join("b.parquet").exists());
+    }
+}
@@ -1,12 +1,135 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
 use clap::{Parser, Subcommand, ValueEnum};
 use log;
+use serde::Deserialize;
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+use crate::utils::parse_partition_spec;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 pub enum Format {
+    /// infer the format from the table path
+    Auto,
+    Parquet,
+    Delta,
+    Json,
+    Csv,
+    Avro,
+}
+
+impl Format {
+    /// Format of a local table: a `_delta_log` folder means delta, otherwise the path
+    /// extension or the one of the first data file of a directory
+    ///
+    /// Remote tables are probed through their object store by `TableContext` instead.
+    pub fn infer_from_uri(uri: &str) -> Result<Format> {
+        if let Some(fmt) = Format::from_extension(uri) {
+            return Ok(fmt);
+        }
+        let path = Path::new(uri.strip_prefix("file://").unwrap_or(uri));
+        if path.join("_delta_log").is_dir() {
+            return Ok(Format::Delta);
+        }
+        if path.is_dir() {
+            let mut names = fs::read_dir(path)?
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .filter(|name| !name.starts_with('_') && !name.starts_with('.'))
+                .collect::<Vec<_>>();
+            names.sort();
+            if let Some(fmt) = names.iter().find_map(|name| Format::from_extension(name)) {
+                return Ok(fmt);
+            }
+        }
+        bail!(
+            "unable to infer the format of {}, expected a _delta_log folder or a \
+             .parquet, .csv, .json (.ndjson, .jsonl) or .avro extension, use --format",
+            uri
+        )
+    }
+
+    /// Format matching a file extension, compression suffixes (`.csv.gz`) aside
+    pub fn from_extension(path: &str) -> Option<Format> {
+        let path = path.trim_end_matches('/');
+        let path = [".gz", ".bz2", ".xz", ".zst"]
+            .iter()
+            .find_map(|ext| path.strip_suffix(ext))
+            .unwrap_or(path);
+        let (_, ext) = path.rsplit_once('.')?;
+        match ext.to_lowercase().as_str() {
+            "parquet" => Some(Format::Parquet),
+            "csv" => Some(Format::Csv),
+            "json" | "ndjson" | "jsonl" => Some(Format::Json),
+            "avro" => Some(Format::Avro),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Compression {
+    Uncompressed,
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum ErrorFormat {
+    Text,
+    /// `{"error_type", "message", "context"}` object, error_type being cancelled, io, arrow,
+    /// object_store, delta, datafusion or usage
+    Json,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum OutputFormat {
+    Csv,
+    Json,
     Parquet,
+    Arrow,
+    Sqlite,
     Delta,
 }
 
+/// How query results are printed on stdout
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum PrintFormat {
+    /// pretty table, shown in the TUI unless --no-tui
+    Table,
+    /// newline delimited json rows
+    Json,
+    Csv,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum ParquetCompression {
+    Snappy,
+    Gzip,
+    Zstd,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum WriteMode {
+    Append,
+    Overwrite,
+}
+
+/// commit coordination of delta tables stored on s3
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum LogStore {
+    /// plain s3, no locking
+    Default,
+    /// plain s3, explicitly allowing unsafe renames for single writer tables
+    UnsafeRename,
+    /// dynamodb locking, table name read from DELTA_DYNAMO_TABLE_NAME (delta_log by default)
+    Dynamodb,
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 pub enum LogLevel {
     Off,
@@ -14,6 +137,31 @@ pub enum LogLevel {
     Debug,
 }
 
+/// Check a `--partitions` spec, see `utils::parse_partition_spec`
+fn partition_spec(spec: &str) -> Result<String, String> {
+    parse_partition_spec(spec)?;
+    Ok(spec.to_string())
+}
+
+/// How binary values are displayed in tables
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum BinaryDisplay {
+    #[default]
+    Hex,
+    Base64,
+    /// hex of the first bytes and the total size
+    Truncate,
+}
+
+/// Colors of the TUI, matching the terminal background
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    #[default]
+    Dark,
+    Light,
+}
+
 /// cli parser
 #[derive(Parser)]
 #[command(name = "adt")]
@@ -21,51 +169,362 @@ pub enum LogLevel {
 pub struct Cli {
     #[arg(short, long, value_enum, default_value_t = LogLevel::Info)]
     log_level: LogLevel,
+    /// format of the error printed on stderr when a command fails
+    #[arg(long, value_enum, global = true, default_value_t = ErrorFormat::Text)]
+    pub error_format: ErrorFormat,
+    /// abort query execution after the given number of seconds
+    #[arg(long, global = true)]
+    pub timeout_secs: Option<u64>,
+    /// TUI colors, overriding the theme of $XDG_CONFIG_HOME/adt/tui.toml (dark by default)
+    #[arg(long, value_enum, global = true)]
+    pub theme: Option<Theme>,
     #[command(subcommand)]
     pub command: Commands,
 }
 
 #[derive(Subcommand)]
 pub enum Commands {
-    /// view (and export) parquet or delta tables
+    /// view (and export) parquet, delta, json, csv or avro tables
     View {
         table_path: String,
-        #[arg(short, long, value_enum, default_value_t = Format::Delta)]
+        #[arg(short, long, value_enum, default_value_t = Format::Auto)]
         format: Format,
         #[arg(short, long, default_value_t = String::from("select * from tbl"))]
         query: String,
+        /// maximum number of rows, 0 for all of them
         #[arg(short, long, default_value_t = 50)]
         limit: usize,
-        #[arg(short, long)]
+        /// fetch all rows, same as --limit 0
+        #[arg(long, default_value_t = false, conflicts_with = "limit")]
+        no_limit: bool,
+        /// partition columns of listing tables, as name:type pairs, e.g. year:int,day:date
+        #[arg(short, long, value_parser = partition_spec)]
         partitions: Option<String>,
         #[arg(long, default_value_t = false)]
         no_tui: bool,
         #[arg(short, long)]
         output_path: Option<String>,
+        /// show arrow types alongside column names in the header
+        #[arg(long, default_value_t = false)]
+        show_types: bool,
+        /// local directory caching remote parquet files between runs
+        #[arg(long)]
+        cache_dir: Option<String>,
+        /// maximum size of the local cache in MiB
+        #[arg(long, default_value_t = 1024)]
+        cache_size_mb: usize,
+        /// disable parquet filter pushdown (debugging aid)
+        #[arg(long, default_value_t = false)]
+        no_pushdown: bool,
+        /// flatten nested fields into underscore-joined top level columns
+        #[arg(long, default_value_t = false)]
+        flatten: bool,
+        /// print files and row groups pruned vs scanned after execution
+        #[arg(long, default_value_t = false)]
+        explain_pruning: bool,
+        /// table name used when exporting to a sqlite database (.db/.sqlite)
+        #[arg(long, default_value_t = String::from("results"))]
+        table: String,
+        /// print the schema resolved by the table provider
+        #[arg(long, default_value_t = false)]
+        explain_schema: bool,
+        /// csv/json files compression, inferred from the file extension by default
+        #[arg(long, value_enum)]
+        compression: Option<Compression>,
+        /// file containing a predicate applied as a WHERE clause over the query
+        #[arg(long)]
+        filter_file: Option<String>,
+        /// sql predicate applied as a WHERE clause over the query, e.g.
+        /// "event_date = '2024-01-01'", repeatable; pushed down to the table scan
+        #[arg(long = "where")]
+        where_: Vec<String>,
+        /// comma separated col=value filters on partition columns, e.g. year=2020
+        #[arg(long)]
+        partition_filter: Option<String>,
+        /// interactively pick the columns to query, replacing --query
+        #[arg(long, default_value_t = false)]
+        pick_columns: bool,
+        /// only return distinct rows, applied before --limit
+        #[arg(long, default_value_t = false)]
+        distinct: bool,
+        /// retry loading a delta table up to N times on transient log read failures
+        #[arg(long, default_value_t = 0)]
+        retry_on_conflict: u32,
+        /// infer listing table schemas from the first N files only, delta schemas come
+        /// from the log
+        #[arg(long)]
+        sample_files: Option<usize>,
+        /// comma separated columns to project from tbl, nested fields as dotted paths
+        /// (user.id), instead of writing a --query
+        #[arg(short, long, conflicts_with = "query")]
+        columns: Option<String>,
+        /// nesting levels of struct/list/map values to display before summarizing them
+        #[arg(long)]
+        max_depth: Option<usize>,
+        /// how binary values are displayed, exports keeping the original bytes
+        #[arg(long, value_enum, default_value_t = BinaryDisplay::Hex)]
+        binary_display: BinaryDisplay,
+        /// truncate displayed string values to N chars, exports keeping the full values
+        #[arg(long)]
+        max_column_width: Option<usize>,
+        /// only push projections to the scan, not predicates (debugging aid)
+        #[arg(long, default_value_t = false)]
+        projection_pushdown_only: bool,
+        /// encoding of uncompressed local csv/json files, utf-8 by default. Any WHATWG label:
+        /// latin1, iso-8859-1..16, windows-1250..1258, shift_jis, euc-jp, gbk, gb18030, big5,
+        /// euc-kr, koi8-r, utf-16le, utf-16be...
+        #[arg(long)]
+        encoding: Option<String>,
+        /// csv field delimiter, comma by default
+        #[arg(long)]
+        csv_delimiter: Option<char>,
+        /// csv quote character, double quote by default
+        #[arg(long)]
+        csv_quote: Option<char>,
+        /// csv escape character, none by default
+        #[arg(long)]
+        csv_escape: Option<char>,
+        /// csv files have no header line, columns are named column_1, column_2...
+        #[arg(long, default_value_t = false)]
+        csv_no_header: bool,
+        /// comma separated columns to group by, projected before the --agg aggregates
+        #[arg(long, conflicts_with = "columns")]
+        group_by: Option<String>,
+        /// comma separated aggregate expressions, count(*) by default when grouping
+        #[arg(long, conflicts_with = "columns")]
+        agg: Option<String>,
+        /// comma separated sort keys applied over the query, col[:asc|desc] or SQL, e.g.
+        /// "amount:desc,id" or "count(*) desc". col is a result column: --rename target,
+        /// aggregate alias or user.id for --columns user.id
+        #[arg(long)]
+        order_by: Option<String>,
+        /// extra table to join against, as name=path[:format] (csv by default), repeatable
+        #[arg(long)]
+        lookup: Vec<String>,
+        /// keep rows in file order when no order by is given, scans files sequentially (slower)
+        #[arg(long, default_value_t = false)]
+        preserve_order: bool,
+        /// rename a result column, as old=new, repeatable
+        #[arg(long)]
+        rename: Vec<String>,
+        /// write results incrementally to stdout as an arrow ipc stream instead of displaying
+        /// them, use with --log-level off as logs are printed on stdout
+        #[arg(long, default_value_t = false)]
+        output_batches_as_arrow_stream: bool,
+        /// delta log store used on s3, must match how the table writers coordinate commits
+        #[arg(long, value_enum)]
+        log_store: Option<LogStore>,
+        /// s3 endpoint, e.g. http://localhost:9000 for MinIO, AWS_ENDPOINT by default
+        #[arg(long)]
+        s3_endpoint: Option<String>,
+        /// s3 region, AWS_REGION by default
+        #[arg(long)]
+        s3_region: Option<String>,
+        /// approximate number of distinct values of a column (see --exact)
+        #[arg(long, conflicts_with = "columns")]
+        count_distinct: Option<String>,
+        /// count distinct values exactly, can be expensive on large tables
+        #[arg(long, default_value_t = false, requires = "count_distinct")]
+        exact: bool,
+        /// export format, inferred from the --output-path extension by default
+        #[arg(long, value_enum, requires = "output_path")]
+        to: Option<OutputFormat>,
+        /// how a delta export commits to an existing table, new tables are unpartitioned
+        #[arg(long, value_enum, default_value_t = WriteMode::Append)]
+        mode: WriteMode,
+        /// compression codec of parquet exports, datafusion default (zstd) otherwise
+        #[arg(long, value_enum)]
+        parquet_compression: Option<ParquetCompression>,
+        /// show the first and last --limit rows along --order-by, all rows when there are
+        /// fewer than twice --limit
+        #[arg(long, default_value_t = false, requires = "order_by")]
+        sample_head_tail: bool,
+        /// delta table version to query, latest by default
+        #[arg(long)]
+        version_as_of: Option<i64>,
+        /// query the delta table version active at an RFC3339 instant, e.g. 2024-01-01T00:00:00Z
+        #[arg(long, conflicts_with = "version_as_of")]
+        timestamp_as_of: Option<DateTime<Utc>>,
+        /// format of the results printed on stdout, independent of --output-path
+        #[arg(long, value_enum, default_value_t = PrintFormat::Table)]
+        output: PrintFormat,
+        /// write json as a single array rather than one object per line, for both
+        /// --output json and json exports
+        #[arg(long, default_value_t = false)]
+        json_array: bool,
+        /// print the row count, batch count and in-memory size of the result on stderr
+        #[arg(long, default_value_t = false)]
+        stats: bool,
     },
     /// execute sql file
-    Execute { sql_file: String },
-    /// print parquet or delta table schema
+    Execute {
+        sql_file: String,
+        /// cache the schemas inferred for external listing tables under this directory,
+        /// entries are invalidated when the table files change
+        #[arg(long)]
+        cache_dir: Option<String>,
+    },
+    /// interactive sql prompt, statements are terminated by `;`
+    ///
+    /// `\dt` lists tables, `\d <table>` describes one, `\q` quits.
+    Repl,
+    /// print parquet, delta, json, csv or avro table schema
     Schema {
         table_path: String,
-        #[arg(short, long, value_enum, default_value_t = Format::Delta)]
+        #[arg(short, long, value_enum, default_value_t = Format::Auto)]
+        format: Format,
+        /// partition columns of listing tables, as name:type pairs, e.g. year:int,day:date
+        #[arg(short, long, value_parser = partition_spec)]
+        partitions: Option<String>,
+        #[arg(long, default_value_t = false)]
+        no_tui: bool,
+        /// print the schema resolved by the table provider
+        #[arg(long, default_value_t = false)]
+        explain_schema: bool,
+        /// csv/json files compression, inferred from the file extension by default
+        #[arg(long, value_enum)]
+        compression: Option<Compression>,
+        /// delta table version to read the schema from, latest by default
+        #[arg(long, alias = "version")]
+        version_as_of: Option<i64>,
+        /// read the delta table version active at an RFC3339 instant, e.g. 2024-01-01T00:00:00Z
+        #[arg(long, conflicts_with = "version_as_of")]
+        timestamp_as_of: Option<DateTime<Utc>>,
+        /// retry loading a delta table up to N times on transient log read failures
+        #[arg(long, default_value_t = 0)]
+        retry_on_conflict: u32,
+        /// infer listing table schemas from the first N files only, delta schemas come
+        /// from the log
+        #[arg(long)]
+        sample_files: Option<usize>,
+        /// encoding of uncompressed local csv/json files, utf-8 by default. Any WHATWG label:
+        /// latin1, iso-8859-1..16, windows-1250..1258, shift_jis, euc-jp, gbk, gb18030, big5,
+        /// euc-kr, koi8-r, utf-16le, utf-16be...
+        #[arg(long)]
+        encoding: Option<String>,
+        /// csv field delimiter, comma by default
+        #[arg(long)]
+        csv_delimiter: Option<char>,
+        /// csv quote character, double quote by default
+        #[arg(long)]
+        csv_quote: Option<char>,
+        /// csv escape character, none by default
+        #[arg(long)]
+        csv_escape: Option<char>,
+        /// csv files have no header line, columns are named column_1, column_2...
+        #[arg(long, default_value_t = false)]
+        csv_no_header: bool,
+        /// delta log store used on s3, must match how the table writers coordinate commits
+        #[arg(long, value_enum)]
+        log_store: Option<LogStore>,
+        /// s3 endpoint, e.g. http://localhost:9000 for MinIO, AWS_ENDPOINT by default
+        #[arg(long)]
+        s3_endpoint: Option<String>,
+        /// s3 region, AWS_REGION by default
+        #[arg(long)]
+        s3_region: Option<String>,
+        /// also print the delta table id, name, partition columns, configuration and
+        /// protocol versions
+        #[arg(long, default_value_t = false)]
+        metadata: bool,
+        /// print per column null count, min and max from delta log or parquet footer
+        /// statistics instead of the plain schema, without scanning data
+        #[arg(long, default_value_t = false)]
+        with_stats: bool,
+    },
+    /// print the size on disk and file count of a table's active data
+    Size {
+        table_path: String,
+        #[arg(short, long, value_enum, default_value_t = Format::Auto)]
+        format: Format,
+        /// partition columns of listing tables, as name:type pairs, e.g. year:int,day:date
+        #[arg(short, long, value_parser = partition_spec)]
+        partitions: Option<String>,
+    },
+    /// print rows masked by deletion vectors per file of a delta table
+    DeletionVectors {
+        table_path: String,
+        #[arg(long, default_value_t = false)]
+        no_tui: bool,
+    },
+    /// print active files, bytes, rows, partitions and deleted rows of a delta table
+    Stats {
+        table_path: String,
+        #[arg(long, default_value_t = false)]
+        no_tui: bool,
+    },
+    /// print the commit history of a delta table, latest first
+    History {
+        table_path: String,
+        #[arg(short, long, value_enum, default_value_t = Format::Auto)]
         format: Format,
+        /// number of commits to print, all by default
         #[arg(short, long)]
+        limit: Option<usize>,
+        #[arg(long, default_value_t = false)]
+        no_tui: bool,
+    },
+    /// list the files a delta vacuum would remove, nothing is deleted
+    Vacuum {
+        table_path: String,
+        /// keep files removed from the table less than this many hours ago
+        #[arg(long, default_value_t = 168)]
+        retention_hours: u64,
+        /// required, only listing removable files is supported
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+        #[arg(long, default_value_t = false)]
+        no_tui: bool,
+    },
+    /// compare two tables and print added, removed and changed rows
+    Diff {
+        left_path: String,
+        right_path: String,
+        #[arg(short, long, value_enum, default_value_t = Format::Auto)]
+        format: Format,
+        /// comma separated key columns matching rows between both tables, printing one
+        /// "diff, <keys>, column, old_value, new_value" row per added/removed row or changed
+        /// column, both tables then needing the same columns
+        #[arg(short, long)]
+        key: Option<String>,
+        /// match null keys with each other instead of reporting them as added/removed
+        #[arg(long, default_value_t = false, requires = "key")]
+        null_equals_null: bool,
+        #[arg(short, long, default_value_t = 50)]
+        limit: usize,
+        /// partition columns of listing tables, as name:type pairs, e.g. year:int,day:date
+        #[arg(short, long, value_parser = partition_spec)]
         partitions: Option<String>,
         #[arg(long, default_value_t = false)]
         no_tui: bool,
     },
-    /// Print logical plan
+    /// print versions of the key underlying crates as json
+    VersionInfo,
+    /// Print logical and physical plans
     Explain {
         table_path: String,
-        #[arg(short, long, value_enum, default_value_t = Format::Delta)]
+        #[arg(short, long, value_enum, default_value_t = Format::Auto)]
         format: Format,
         #[arg(short, long, default_value_t = String::from("select * from tbl"))]
         query: String,
         #[arg(short, long, default_value_t = 50)]
         limit: usize,
-        #[arg(short, long)]
+        /// partition columns of listing tables, as name:type pairs, e.g. year:int,day:date
+        #[arg(short, long, value_parser = partition_spec)]
         partitions: Option<String>,
+        /// disable parquet filter pushdown (debugging aid)
+        #[arg(long, default_value_t = false)]
+        no_pushdown: bool,
+        /// file containing a predicate applied as a WHERE clause over the query
+        #[arg(long)]
+        filter_file: Option<String>,
+        /// sql predicate applied as a WHERE clause over the query, e.g.
+        /// "event_date = '2024-01-01'", repeatable; pushed down to the table scan
+        #[arg(long = "where")]
+        where_: Vec<String>,
+        /// run the query and print the physical plan with per operator metrics
+        #[arg(long, default_value_t = false)]
+        analyze: bool,
     },
 }
 
@@ -1,48 +1,104 @@
-use anyhow::Result;
-use datafusion::logical_expr::{DdlStatement, LogicalPlan};
+use anyhow::{anyhow, Context, Result};
+use arrow::datatypes::Schema;
+use arrow::ipc::reader::FileReader;
+use arrow::ipc::writer::FileWriter;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use datafusion::common::DFSchema;
+use datafusion::datasource::listing::ListingTableUrl;
+use datafusion::datasource::provider::TableProviderFactory;
+use datafusion::datasource::TableProvider;
+use datafusion::error::DataFusionError;
+use datafusion::logical_expr::{CreateExternalTable, DdlStatement, LogicalPlan};
 use datafusion::prelude::*;
 use deltalake::datafusion::execution::context::{SessionContext, SessionState};
 use deltalake::datafusion::execution::runtime_env::{RuntimeConfig, RuntimeEnv};
 use deltalake::datafusion::prelude::SessionConfig;
-use deltalake::delta_datafusion::DeltaTableFactory;
+use deltalake::DeltaTableBuilder;
+use futures::TryStreamExt;
+use log::{debug, warn};
 use object_store;
-use object_store::aws::AmazonS3Builder;
+use object_store::azure::MicrosoftAzureBuilder;
+use object_store::http::HttpBuilder;
+use object_store::ObjectMeta;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::PathBuf;
 use std::sync::Arc;
 use url::Url;
 
-use crate::utils::ensure_scheme;
+use crate::delta;
+use crate::utils::{
+    compression_from_path, ensure_scheme, gcs_builder, s3_builder, AZURE_STORE_ERROR,
+};
+/// File types of external tables backed by datafusion listing tables
+const LISTING_FILE_TYPES: [&str; 5] = ["PARQUET", "CSV", "JSON", "AVRO", "ARROW"];
+
 pub struct SQLContext {
     ctx: SessionContext,
+    schema_cache: Option<SchemaCache>,
 }
 
 impl SQLContext {
-    pub fn new() -> Self {
+    pub fn new() -> Result<Self> {
         let cfg = RuntimeConfig::new();
-        let env = RuntimeEnv::new(cfg).unwrap();
-        let ses = SessionConfig::new().with_information_schema(true);
+        let env = RuntimeEnv::new(cfg)?;
+        let ses = SessionConfig::new()
+            .with_information_schema(true)
+            .set_bool("datafusion.execution.parquet.pushdown_filters", true);
         let mut state = SessionState::new_with_config_rt(ses, Arc::new(env));
         state
             .table_factories_mut()
-            .insert("DELTA".to_string(), Arc::new(DeltaTableFactory {}));
-        Self {
+            .insert("DELTA".to_string(), Arc::new(VersionedDeltaTableFactory {}));
+        Ok(Self {
             ctx: SessionContext::new_with_state(state),
-        }
+            schema_cache: None,
+        })
+    }
+
+    /// Reuse the schemas of external listing tables cached under `cache_dir` instead of
+    /// inferring them on each run
+    pub fn with_schema_cache(mut self, cache_dir: &Option<String>) -> Self {
+        self.schema_cache = cache_dir.as_ref().map(|dir| SchemaCache {
+            dir: PathBuf::from(dir),
+        });
+        self
+    }
+
+    /// Version of the files under `location`: their count and latest modification time
+    async fn files_version(&self, location: &str) -> Result<String> {
+        let url = ListingTableUrl::parse(location)?;
+        let store = self.ctx.runtime_env().object_store(&url)?;
+        let files: Vec<ObjectMeta> = url
+            .list_all_files(&self.ctx.state(), store.as_ref(), "")
+            .await?
+            .try_collect()
+            .await?;
+        let modified = files.iter().map(|meta| meta.last_modified).max();
+        Ok(format!(
+            "{}:{}",
+            files.len(),
+            modified.map(|m| m.to_rfc3339()).unwrap_or_default()
+        ))
     }
 
-    async fn register_object_store(&self, location: &String, file_type: &String) -> Result<()> {
-        let url = ensure_scheme(location).unwrap();
+    async fn register_object_store(
+        &self,
+        location: &String,
+        file_type: &String,
+        options: &HashMap<String, String>,
+    ) -> Result<()> {
+        let url =
+            ensure_scheme(location).map_err(|_| anyhow!("invalid table location {}", location))?;
         match (url.scheme(), file_type.as_str()) {
             ("s3", ft) => {
-                let s3 = AmazonS3Builder::from_env()
-                    .with_bucket_name(
-                        url.host_str()
-                            .expect("failed to extract host/bucket from path"),
-                    )
+                // endpoint, region and credentials options take precedence over the env
+                let s3 = s3_builder(&url, options)?
                     .build()
-                    .expect("Unable to create S3 object store");
+                    .context("Unable to create S3 object store")?;
                 let s3_url =
                     Url::parse(&url[url::Position::BeforeScheme..url::Position::AfterHost])
-                        .expect("Unable to get bucket based S3 url");
+                        .context("Unable to get bucket based S3 url")?;
                 let _ = self
                     .ctx
                     .runtime_env()
@@ -53,18 +109,133 @@ impl SQLContext {
                     _ => (),
                 }
             }
+            ("gs" | "gcs", ft) => {
+                // credentials options take precedence over the env
+                let gcs = gcs_builder(&url, options)?
+                    .build()
+                    .context("Unable to create GCS object store")?;
+                let gcs_url =
+                    Url::parse(&url[url::Position::BeforeScheme..url::Position::AfterHost])
+                        .context("Unable to get bucket based GCS url")?;
+                let _ = self
+                    .ctx
+                    .runtime_env()
+                    .object_store_registry
+                    .register_store(&gcs_url, Arc::new(gcs));
+                match ft {
+                    "DELTA" => deltalake::gcp::register_handlers(None),
+                    _ => (),
+                }
+            }
+            ("abfss" | "abfs" | "wasbs" | "wasb", ft) => {
+                // container and account are parsed from the url
+                let azure = MicrosoftAzureBuilder::from_env()
+                    .with_url(url.as_str())
+                    .build()
+                    .context(AZURE_STORE_ERROR)?;
+                let _ = self
+                    .ctx
+                    .runtime_env()
+                    .object_store_registry
+                    .register_store(&url, Arc::new(azure));
+                match ft {
+                    "DELTA" => deltalake::azure::register_handlers(None),
+                    _ => (),
+                }
+            }
+            ("http" | "https", _) => {
+                // object paths are relative to the server origin
+                let http = HttpBuilder::new()
+                    .with_url(&url[..url::Position::BeforePath])
+                    .build()
+                    .context("Unable to create HTTP object store")?;
+                let _ = self
+                    .ctx
+                    .runtime_env()
+                    .object_store_registry
+                    .register_store(&url, Arc::new(http));
+            }
             _ => (),
         }
         Ok(())
     }
 
     pub async fn execute_logical_plan(&self, plan: LogicalPlan) -> Result<DataFrame> {
-        if let LogicalPlan::Ddl(DdlStatement::CreateExternalTable(cmd)) = &plan {
-            println!("file type: {:?}", cmd.file_type);
-            self.register_object_store(&cmd.location, &cmd.file_type)
+        let mut plan = match plan {
+            LogicalPlan::Ddl(DdlStatement::CreateExternalTable(mut cmd)) => {
+                // compressed csv/json files, e.g. `events.csv.gz`, without COMPRESSION TYPE
+                let compression = compression_from_path(&cmd.location);
+                if matches!(cmd.file_type.as_str(), "CSV" | "JSON")
+                    && compression.is_compressed()
+                    && !cmd.options.contains_key("format.compression")
+                {
+                    cmd.options.insert(
+                        "format.compression".to_string(),
+                        compression.get_variant().to_string(),
+                    );
+                }
+                if cmd.file_type == "CSV" {
+                    // accept csv options without their format. prefix
+                    for key in ["delimiter", "quote", "escape", "has_header"] {
+                        if let Some(value) = cmd.options.remove(key) {
+                            cmd.options.insert(format!("format.{}", key), value);
+                        }
+                    }
+                }
+                LogicalPlan::Ddl(DdlStatement::CreateExternalTable(cmd))
+            }
+            plan => plan,
+        };
+        // table whose inferred schema is to be cached once created
+        let mut uncached = None;
+        if let LogicalPlan::Ddl(DdlStatement::CreateExternalTable(cmd)) = &mut plan {
+            // each DDL registers the store of its own location, e.g. several s3 buckets
+            debug!("register {} table at {}", cmd.file_type, cmd.location);
+            self.register_object_store(&cmd.location, &cmd.file_type, &cmd.options)
                 .await?;
+            if let Some(cache) = &self.schema_cache {
+                if LISTING_FILE_TYPES.contains(&cmd.file_type.as_str())
+                    && cmd.schema.fields().is_empty()
+                {
+                    let version = self.files_version(&cmd.location).await?;
+                    let key = SchemaKey {
+                        location: &cmd.location,
+                        file_type: &cmd.file_type,
+                        options: &cmd.options,
+                    };
+                    match cache.get(&key, &version) {
+                        Some(schema) => {
+                            debug!("cached schema for {}", cmd.location);
+                            cmd.schema = Arc::new(DFSchema::try_from(schema)?);
+                        }
+                        None => {
+                            uncached = Some((
+                                cmd.name.clone(),
+                                cmd.location.clone(),
+                                cmd.file_type.clone(),
+                                cmd.options.clone(),
+                                version,
+                            ))
+                        }
+                    }
+                }
+            }
         }
         let df = self.ctx.execute_logical_plan(plan).await?;
+        if let (Some(cache), Some((name, location, file_type, options, version))) =
+            (&self.schema_cache, uncached)
+        {
+            let schema = self.ctx.table_provider(name).await?.schema();
+            let key = SchemaKey {
+                location: &location,
+                file_type: &file_type,
+                options: &options,
+            };
+            // a cache write failure only costs a later inference
+            if let Err(err) = cache.put(&key, &version, &schema) {
+                warn!("unable to cache schema of {}: {:#}", location, err);
+            }
+        }
         Ok(df)
     }
 
@@ -78,3 +249,214 @@ impl SQLContext {
         self.execute_logical_plan(plan).await
     }
 }
+
+/// On disk cache of listing table schemas, one arrow ipc file per table location, file
+/// type and options
+///
+/// Each entry records the version of the table files it was inferred from, see
+/// `SQLContext::files_version`, and is ignored once they change.
+struct SchemaCache {
+    dir: PathBuf,
+}
+
+/// Identifies the cached schema of an external table
+struct SchemaKey<'a> {
+    location: &'a str,
+    file_type: &'a str,
+    options: &'a HashMap<String, String>,
+}
+
+impl SchemaKey<'_> {
+    /// FNV-1a hash of the key, stable across builds unlike `DefaultHasher`
+    fn hash(&self) -> u64 {
+        // sorted options, whose map iteration order is random
+        let options: BTreeMap<_, _> = self.options.iter().collect();
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let mut write = |bytes: &[u8]| {
+            // separator so that ("ab", "c") and ("a", "bc") differ
+            for byte in bytes.iter().chain([0xff].iter()) {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        };
+        write(self.location.as_bytes());
+        write(self.file_type.as_bytes());
+        for (key, value) in options {
+            write(key.as_bytes());
+            write(value.as_bytes());
+        }
+        hash
+    }
+}
+
+impl SchemaCache {
+    const VERSION_KEY: &'static str = "adt.files_version";
+
+    fn path(&self, key: &SchemaKey) -> PathBuf {
+        self.dir.join(format!("{:016x}.arrow", key.hash()))
+    }
+
+    fn get(&self, key: &SchemaKey, version: &str) -> Option<Schema> {
+        let file = fs::File::open(self.path(key)).ok()?;
+        let reader = FileReader::try_new(file, None).ok()?;
+        match reader.custom_metadata().get(Self::VERSION_KEY) {
+            Some(cached) if cached == version => Some(reader.schema().as_ref().clone()),
+            _ => None,
+        }
+    }
+
+    fn put(&self, key: &SchemaKey, version: &str, schema: &Schema) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let file = fs::File::create(self.path(key))?;
+        let mut writer = FileWriter::try_new(file, schema)?;
+        writer.write_metadata(Self::VERSION_KEY, version);
+        writer.finish()?;
+        Ok(())
+    }
+}
+
+/// Delta table factory reading the version given by the `version` option, or the one active
+/// at the RFC3339 `timestamp_as_of` option, latest by default
+///
+/// e.g. `create external table t stored as delta location '...' options ('version' '12')`,
+/// the other options are passed to the storage backend.
+struct VersionedDeltaTableFactory {}
+
+#[async_trait]
+impl TableProviderFactory for VersionedDeltaTableFactory {
+    async fn create(
+        &self,
+        _state: &SessionState,
+        cmd: &CreateExternalTable,
+    ) -> datafusion::error::Result<Arc<dyn TableProvider>> {
+        let mut options = cmd.options.clone();
+        let version = options
+            .remove("version")
+            .map(|v| v.parse::<i64>())
+            .transpose()
+            .map_err(|e| DataFusionError::Plan(format!("invalid delta table version: {}", e)))?;
+        let timestamp = options
+            .remove("timestamp_as_of")
+            .map(|ts| ts.parse::<DateTime<Utc>>())
+            .transpose()
+            .map_err(|e| DataFusionError::Plan(format!("invalid delta table timestamp: {}", e)))?;
+        let builder = DeltaTableBuilder::from_uri(&cmd.location).with_storage_options(options);
+        let builder = match (version, timestamp) {
+            (Some(_), Some(_)) => {
+                return Err(DataFusionError::Plan(
+                    "delta table version and timestamp_as_of are mutually exclusive".to_string(),
+                ))
+            }
+            (Some(version), None) => builder.with_version(version),
+            (None, Some(timestamp)) => builder.with_timestamp(timestamp),
+            (None, None) => builder,
+        };
+        let table = builder
+            .load()
+            .await
+            .map_err(|e| DataFusionError::External(Box::new(e)))?;
+        delta::table_provider(table).map_err(|e| DataFusionError::External(e.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::Int64Array;
+
+    #[tokio::test]
+    async fn select_one() {
+        let ctx = SQLContext::new().unwrap();
+        let batches = ctx.sql("select 1").await.unwrap().collect().await.unwrap();
+        let one = batches[0]
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+        assert_eq!(one.value(0), 1);
+    }
+
+    async fn column_names(ctx: &SQLContext, location: &str, options: &str) -> Vec<String> {
+        ctx.sql(&format!(
+            "create external table tbl stored as csv location '{}' {}",
+            location, options
+        ))
+        .await
+        .unwrap();
+        let schema = ctx.ctx.table_provider("tbl").await.unwrap().schema();
+        schema.fields().iter().map(|f| f.name().clone()).collect()
+    }
+
+    fn cached_ctx(dir: &tempfile::TempDir) -> SQLContext {
+        let cache_dir = Some(dir.path().join("cache").display().to_string());
+        SQLContext::new().unwrap().with_schema_cache(&cache_dir)
+    }
+
+    #[tokio::test]
+    async fn schema_cache_hit() {
+        let dir = tempfile::tempdir().unwrap();
+        let csv = dir.path().join("data.csv");
+        fs::write(&csv, "a,b\n1,2\n").unwrap();
+        let location = csv.display().to_string();
+        let header = "options ('has_header' 'true')";
+        assert_eq!(
+            column_names(&cached_ctx(&dir), &location, header).await,
+            ["a", "b"]
+        );
+
+        // replace the cached entry to tell it from an inference
+        let ctx = cached_ctx(&dir);
+        let options = HashMap::from([("format.has_header".to_string(), "true".to_string())]);
+        let key = SchemaKey {
+            location: &location,
+            file_type: "CSV",
+            options: &options,
+        };
+        let version = ctx.files_version(&location).await.unwrap();
+        let cache = ctx.schema_cache.as_ref().unwrap();
+        let cached = cache.get(&key, &version).expect("cached schema");
+        let renamed = Schema::new(
+            cached
+                .fields()
+                .iter()
+                .map(|f| f.as_ref().clone().with_name(format!("cached_{}", f.name())))
+                .collect::<Vec<_>>(),
+        );
+        cache.put(&key, &version, &renamed).unwrap();
+        assert_eq!(
+            column_names(&ctx, &location, header).await,
+            ["cached_a", "cached_b"]
+        );
+
+        // other options are cached apart
+        let names = column_names(
+            &cached_ctx(&dir),
+            &location,
+            "options ('has_header' 'false')",
+        );
+        assert_eq!(names.await, ["column_1", "column_2"]);
+    }
+
+    #[tokio::test]
+    async fn schema_cache_invalidated_on_mtime_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let csv = dir.path().join("data.csv");
+        fs::write(&csv, "a,b\n1,2\n").unwrap();
+        let location = csv.display().to_string();
+        let header = "options ('has_header' 'true')";
+        assert_eq!(
+            column_names(&cached_ctx(&dir), &location, header).await,
+            ["a", "b"]
+        );
+
+        // same file count and size, later modification time
+        fs::write(&csv, "c,d\n1,2\n").unwrap();
+        let file = fs::File::options().write(true).open(&csv).unwrap();
+        let later = std::time::SystemTime::now() + std::time::Duration::from_secs(60);
+        file.set_modified(later).unwrap();
+        assert_eq!(
+            column_names(&cached_ctx(&dir), &location, header).await,
+            ["c", "d"]
+        );
+    }
+}
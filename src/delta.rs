@@ -0,0 +1,243 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Result};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::record_batch::RecordBatch;
+use async_trait::async_trait;
+use datafusion::common::ScalarValue;
+use datafusion::datasource::empty::EmptyTable;
+use datafusion::datasource::file_format::parquet::ParquetFormat;
+use datafusion::datasource::file_format::FileFormat;
+use datafusion::datasource::listing::PartitionedFile;
+use datafusion::datasource::physical_plan::FileScanConfig;
+use datafusion::datasource::{TableProvider, TableType};
+use datafusion::execution::context::SessionState;
+use datafusion::execution::object_store::ObjectStoreUrl;
+use datafusion::logical_expr::Expr;
+use datafusion::physical_expr::expressions::{cast, Column};
+use datafusion::physical_expr::PhysicalExpr;
+use datafusion::physical_plan::projection::ProjectionExec;
+use datafusion::physical_plan::ExecutionPlan;
+use deltalake::protocol::SaveMode;
+use deltalake::{DeltaOps, DeltaTable};
+use log::debug;
+use object_store::path::Path;
+use url::Url;
+
+use crate::cli::{LogStore, WriteMode};
+use crate::utils::ensure_scheme;
+
+/// Storage options selecting the delta log store of s3 tables, plain s3 without locking by default
+pub fn log_store_options(log_store: &Option<LogStore>) -> HashMap<String, String> {
+    let option = match log_store {
+        Some(LogStore::UnsafeRename) => Some(("AWS_S3_ALLOW_UNSAFE_RENAME", "true")),
+        Some(LogStore::Dynamodb) => Some(("AWS_S3_LOCKING_PROVIDER", "dynamodb")),
+        Some(LogStore::Default) | None => None,
+    };
+    option
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .into_iter()
+        .collect()
+}
+
+/// Provider scanning the loaded delta `table`, for both registered and external tables
+///
+/// Column mapped tables are scanned by `ColumnMappedTable`, the delta scan reading parquet
+/// columns by logical name.
+pub fn table_provider(table: DeltaTable) -> Result<Arc<dyn TableProvider>> {
+    if table.get_files_count() == 0 {
+        // delta scans without any file group build invalid plans
+        debug!("empty delta table");
+        return Ok(Arc::new(EmptyTable::new(TableProvider::schema(&table))));
+    }
+    let mapping = table
+        .metadata()?
+        .configuration
+        .get("delta.columnMapping.mode")
+        .cloned()
+        .flatten();
+    if let Some(mode) = mapping.filter(|mode| mode != "none") {
+        debug!("delta column mapping ({} mode)", mode);
+        return Ok(Arc::new(ColumnMappedTable::try_new(&table)?));
+    }
+    Ok(Arc::new(table))
+}
+
+/// Physical name of a column mapped field, the logical one without mapping
+fn physical_name(field: &Field) -> &str {
+    field
+        .metadata()
+        .get("delta.columnMapping.physicalName")
+        // metadata values are json encoded by the delta to arrow schema conversion
+        .map(|name| name.trim_matches('"'))
+        .unwrap_or(field.name())
+}
+
+/// `field` as written in data files, nested struct fields renamed too
+fn physical_field(field: &Field) -> Field {
+    let data_type = match field.data_type() {
+        DataType::Struct(children) => {
+            DataType::Struct(children.iter().map(|c| physical_field(c)).collect())
+        }
+        DataType::List(item) => DataType::List(Arc::new(physical_field(item))),
+        other => other.clone(),
+    };
+    Field::new(physical_name(field), data_type, field.is_nullable())
+}
+
+/// Delta table using column mapping (name or id mode), whose data files store columns
+/// under physical names (`col-<uuid>`) instead of the logical ones of the table schema
+///
+/// Files of the snapshot are scanned with their physical schema and columns renamed back
+/// to logical names. Predicates are evaluated after the scan and deletion vectors are not
+/// supported.
+pub struct ColumnMappedTable {
+    /// logical schema, partition columns last
+    schema: SchemaRef,
+    /// physical schema of the data files
+    file_schema: SchemaRef,
+    partition_fields: Vec<Field>,
+    store_url: ObjectStoreUrl,
+    files: Vec<PartitionedFile>,
+}
+
+impl ColumnMappedTable {
+    pub fn try_new(table: &DeltaTable) -> Result<Self> {
+        let partition_columns = &table.metadata()?.partition_columns;
+        let logical = TableProvider::schema(table);
+        let (partition_fields, data_fields): (Vec<Field>, Vec<Field>) = logical
+            .fields()
+            .iter()
+            .map(|f| f.as_ref().clone())
+            .partition(|f| partition_columns.contains(f.name()));
+        let file_schema = Schema::new(data_fields.iter().map(physical_field).collect::<Vec<_>>());
+        let schema = Schema::new([data_fields, partition_fields.clone()].concat());
+
+        let url = ensure_scheme(&table.table_uri())
+            .map_err(|_| anyhow!("invalid delta table uri {}", table.table_uri()))?;
+        let store_url = ObjectStoreUrl::parse(&url[..url::Position::BeforePath])?;
+        let root = url.path().trim_end_matches('/');
+        let mut files = vec![];
+        for add in table.snapshot()?.file_actions()? {
+            if add.deletion_vector.is_some() {
+                bail!("deletion vectors of column mapped delta tables are not supported");
+            }
+            let mut file = PartitionedFile::new(String::new(), add.size as u64);
+            file.object_meta.location = Path::from_url_path(format!("{}/{}", root, add.path))?;
+            // partition values are keyed by physical name
+            file.partition_values = partition_fields
+                .iter()
+                .map(|f| {
+                    let value = add
+                        .partition_values
+                        .get(physical_name(f))
+                        .or_else(|| add.partition_values.get(f.name()))
+                        .cloned()
+                        .flatten();
+                    match value {
+                        Some(value) => ScalarValue::try_from_string(value, f.data_type()),
+                        None => ScalarValue::try_from(f.data_type()),
+                    }
+                })
+                .collect::<datafusion::error::Result<_>>()?;
+            files.push(file);
+        }
+        Ok(Self {
+            schema: Arc::new(schema),
+            file_schema: Arc::new(file_schema),
+            partition_fields,
+            store_url,
+            files,
+        })
+    }
+}
+
+#[async_trait]
+impl TableProvider for ColumnMappedTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    async fn scan(
+        &self,
+        state: &SessionState,
+        projection: Option<&Vec<usize>>,
+        _filters: &[Expr],
+        limit: Option<usize>,
+    ) -> datafusion::error::Result<Arc<dyn ExecutionPlan>> {
+        let partitions = state.config().target_partitions().max(1);
+        let chunk = self.files.len().div_ceil(partitions).max(1);
+        let config = FileScanConfig::new(self.store_url.clone(), self.file_schema.clone())
+            .with_file_groups(self.files.chunks(chunk).map(|c| c.to_vec()).collect())
+            .with_table_partition_cols(self.partition_fields.clone())
+            .with_projection(projection.cloned())
+            .with_limit(limit);
+        let scan = ParquetFormat::default()
+            .create_physical_plan(state, config, None)
+            .await?;
+        // rename the scanned physical columns to their logical names
+        let scanned = scan.schema();
+        let indices = projection
+            .cloned()
+            .unwrap_or_else(|| (0..self.schema.fields().len()).collect());
+        let exprs = indices
+            .iter()
+            .enumerate()
+            .map(|(i, &index)| {
+                let field = self.schema.field(index);
+                let column: Arc<dyn PhysicalExpr> =
+                    Arc::new(Column::new(scanned.field(i).name(), i));
+                // nested fields are renamed by casting structs, matched by position
+                let expr = if scanned.field(i).data_type() == field.data_type() {
+                    column
+                } else {
+                    cast(column, &scanned, field.data_type().clone())?
+                };
+                Ok((expr, field.name().clone()))
+            })
+            .collect::<datafusion::error::Result<Vec<_>>>()?;
+        Ok(Arc::new(ProjectionExec::try_new(exprs, scan)?))
+    }
+}
+
+/// Commit `records` as a new version of the delta table at `path`, creating the table if needed
+///
+/// Appending to or overwriting an existing table keeps its partition columns, new tables are
+/// created unpartitioned. Returns the committed table version.
+pub async fn write_delta(
+    path: &str,
+    records: Vec<RecordBatch>,
+    mode: WriteMode,
+    log_store: &Option<LogStore>,
+) -> Result<i64> {
+    deltalake::aws::register_handlers(None);
+    deltalake::gcp::register_handlers(None);
+    deltalake::azure::register_handlers(None);
+    if Url::parse(path).is_err() {
+        // local tables may not exist yet
+        fs::create_dir_all(path)?;
+    }
+    let url = ensure_scheme(path).map_err(|_| anyhow!("invalid table path {}", path))?;
+    let save_mode = match mode {
+        WriteMode::Append => SaveMode::Append,
+        WriteMode::Overwrite => SaveMode::Overwrite,
+    };
+    let table =
+        DeltaOps::try_from_uri_with_storage_options(url.as_str(), log_store_options(log_store))
+            .await?
+            .write(records)
+            .with_save_mode(save_mode)
+            .await?;
+    Ok(table.version())
+}
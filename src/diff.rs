@@ -0,0 +1,180 @@
+use anyhow::{bail, Result};
+use datafusion::common::DFSchema;
+use datafusion::prelude::*;
+use log::info;
+
+/// Build the query comparing the `left_tbl` and `right_tbl` tables registered in `ctx`.
+///
+/// Without keys, rows are compared as a whole and reported as `removed` (only in left)
+/// or `added` (only in right).
+/// With keys, rows are matched by key and the result has one row per difference:
+/// `diff, <keys>, column, old_value, new_value` where `diff` is `added`, `removed`
+/// or `changed`. Changed rows get one line per differing column, values rendered as
+/// strings; `column`, `old_value` and `new_value` are null for added/removed rows.
+/// Keyed diffs require both tables to have the same column names, in any order, and
+/// fail listing the columns found in a single table otherwise.
+///
+/// Null keys never match with SQL equality, so rows with null keys show up as both
+/// removed and added unless `null_equals_null` is set, matching them with
+/// `is not distinct from` instead. Column values are always compared null-safely, and
+/// so are whole rows without keys, hence `null_equals_null` requires keys.
+pub async fn build_diff_query(
+    ctx: &SessionContext,
+    keys: &[String],
+    null_equals_null: bool,
+) -> Result<String> {
+    if null_equals_null && keys.is_empty() {
+        bail!("null_equals_null requires key columns");
+    }
+    let query = if keys.is_empty() {
+        "select 'removed' as diff, * from (select * from left_tbl except select * from right_tbl) \
+         union all \
+         select 'added' as diff, * from (select * from right_tbl except select * from left_tbl)"
+            .to_string()
+    } else {
+        let schema = ctx.table("left_tbl").await?.schema().clone();
+        let right_schema = ctx.table("right_tbl").await?.schema().clone();
+        for key in keys {
+            if !schema.has_column_with_unqualified_name(key) {
+                bail!("key column {} not found in left table", key);
+            }
+        }
+        // changed values are compared column by column, left columns missing on the right
+        // would fail the query and right only ones would go unreported
+        let only_in = |a: &DFSchema, b: &DFSchema| {
+            a.fields()
+                .iter()
+                .filter(|f| !b.has_column_with_unqualified_name(f.name()))
+                .map(|f| f.name().clone())
+                .collect::<Vec<_>>()
+        };
+        let (left_only, right_only) = (
+            only_in(&schema, &right_schema),
+            only_in(&right_schema, &schema),
+        );
+        if !left_only.is_empty() || !right_only.is_empty() {
+            bail!(
+                "keyed diff requires the same columns in both tables, left only: [{}], right only: [{}]",
+                left_only.join(", "),
+                right_only.join(", ")
+            );
+        }
+        let key_cols = |alias: &str| {
+            keys.iter()
+                .map(|k| format!("{}.{}", alias, ident(k)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let key_eq = if null_equals_null {
+            "is not distinct from"
+        } else {
+            "="
+        };
+        let join_on = keys
+            .iter()
+            .map(|k| format!("l.{} {} r.{}", ident(k), key_eq, ident(k)))
+            .collect::<Vec<_>>()
+            .join(" and ");
+        // keys may be null, flag joined rows explicitly to detect unmatched ones
+        let left_src = "(select *, true as __adt_present from left_tbl)";
+        let right_src = "(select *, true as __adt_present from right_tbl)";
+        let no_change = "cast(null as varchar) as \"column\", \
+                         cast(null as varchar) as old_value, \
+                         cast(null as varchar) as new_value";
+        let mut parts = vec![
+            format!(
+                "select 'removed' as diff, {}, {} from left_tbl l left join {} r on {} where r.__adt_present is null",
+                key_cols("l"), no_change, right_src, join_on
+            ),
+            format!(
+                "select 'added' as diff, {}, {} from right_tbl r left join {} l on {} where l.__adt_present is null",
+                key_cols("r"), no_change, left_src, join_on
+            ),
+        ];
+        for field in schema.fields() {
+            if keys.contains(field.name()) {
+                continue;
+            }
+            let name = ident(field.name());
+            parts.push(format!(
+                "select 'changed' as diff, {}, {} as \"column\", \
+                 cast(l.{} as varchar) as old_value, cast(r.{} as varchar) as new_value \
+                 from left_tbl l join right_tbl r on {} where l.{} is distinct from r.{}",
+                key_cols("l"),
+                literal(field.name()),
+                name,
+                name,
+                join_on,
+                name,
+                name
+            ));
+        }
+        parts.join(" union all ")
+    };
+    info!("diff query: {}", query);
+    Ok(query)
+}
+
+/// Quote `name` as a sql identifier
+fn ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Quote `value` as a sql string literal
+fn literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+
+    async fn diff_ctx(left: &str, right: &str) -> SessionContext {
+        let ctx = SessionContext::new();
+        for (name, values) in [("left_tbl", left), ("right_tbl", right)] {
+            ctx.sql(&format!(
+                "create view {} as select * from (values {})",
+                name, values
+            ))
+            .await
+            .unwrap();
+        }
+        ctx
+    }
+
+    #[tokio::test]
+    async fn keyed_diff() {
+        let ctx = diff_ctx(
+            "(1, 'alice', 10), (2, 'bob', 20), (3, 'carol', 30)",
+            "(1, 'alice', 10), (2, 'bobby', 21), (4, 'dave', 40)",
+        )
+        .await;
+        let query = build_diff_query(&ctx, &["column1".to_string()], false)
+            .await
+            .unwrap();
+        let sorted = format!("select * from ({}) order by column1, \"column\"", query);
+        let batches = ctx.sql(&sorted).await.unwrap().collect().await.unwrap();
+        assert_eq!(
+            pretty_format_batches(&batches).unwrap().to_string(),
+            "\
++---------+---------+---------+-----------+-----------+
+| diff    | column1 | column  | old_value | new_value |
++---------+---------+---------+-----------+-----------+
+| changed | 2       | column2 | bob       | bobby     |
+| changed | 2       | column3 | 20        | 21        |
+| removed | 3       |         |           |           |
+| added   | 4       |         |           |           |
++---------+---------+---------+-----------+-----------+"
+        );
+    }
+
+    #[tokio::test]
+    async fn keyed_diff_requires_same_columns() {
+        let ctx = diff_ctx("(1, 'alice', 10)", "(1, 'alice')").await;
+        let err = build_diff_query(&ctx, &["column1".to_string()], false)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("left only: [column3]"), "{}", err);
+    }
+}
@@ -0,0 +1,80 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+use encoding_rs::Encoding;
+use log::{info, warn};
+use tempfile::TempDir;
+use url::Url;
+
+use crate::utils::compression_from_path;
+
+/// UTF-8 copy of a local file or directory, deleted when dropped
+pub struct TranscodedCopy {
+    _dir: TempDir,
+    pub url: Url,
+}
+
+/// Transcode the local file or directory at `source` from the `label` encoding to UTF-8.
+///
+/// `label` is any WHATWG encoding label, e.g. `latin1`, `windows-1252`, `shift_jis` or
+/// `utf-16le`. Directories are copied recursively so that hive partitions are preserved.
+/// Only uncompressed local files are supported, compressed ones being rejected.
+pub fn transcode_to_utf8(source: &Url, label: &str) -> Result<TranscodedCopy> {
+    let encoding = Encoding::for_label(label.as_bytes())
+        .ok_or_else(|| anyhow!("unsupported encoding {}", label))?;
+    if source.scheme() != "file" {
+        bail!("encoding conversion is only supported for local files");
+    }
+    let src = source
+        .to_file_path()
+        .map_err(|_| anyhow!("invalid local path {}", source))?;
+    let dir = tempfile::tempdir()?;
+    info!(
+        "transcode {} from {} to utf-8 in {}",
+        src.display(),
+        encoding.name(),
+        dir.path().display()
+    );
+    let url = if src.is_file() {
+        let dest = dir.path().join(src.file_name().unwrap_or_default());
+        transcode_file(&src, &dest, encoding)?;
+        Url::from_file_path(&dest)
+    } else {
+        transcode_dir(&src, dir.path(), encoding)?;
+        Url::from_directory_path(dir.path())
+    }
+    .map_err(|_| anyhow!("invalid transcoded path"))?;
+    Ok(TranscodedCopy { _dir: dir, url })
+}
+
+fn transcode_dir(src: &Path, dest: &Path, encoding: &'static Encoding) -> Result<()> {
+    for entry in fs::read_dir(src)? {
+        let path = entry?.path();
+        let target = dest.join(path.file_name().unwrap_or_default());
+        if path.is_dir() {
+            fs::create_dir_all(&target)?;
+            transcode_dir(&path, &target, encoding)?;
+        } else {
+            transcode_file(&path, &target, encoding)?;
+        }
+    }
+    Ok(())
+}
+
+fn transcode_file(src: &Path, dest: &Path, encoding: &'static Encoding) -> Result<()> {
+    if compression_from_path(&src.to_string_lossy()).is_compressed() {
+        bail!("unable to transcode compressed file {}", src.display());
+    }
+    let bytes = fs::read(src)?;
+    let (text, _, had_errors) = encoding.decode(&bytes);
+    if had_errors {
+        warn!(
+            "{} contains invalid {} sequences",
+            src.display(),
+            encoding.name()
+        );
+    }
+    fs::write(dest, text.as_bytes())?;
+    Ok(())
+}
@@ -0,0 +1,29 @@
+use anyhow::Result;
+use arrow::record_batch::RecordBatch;
+
+pub mod cache;
+pub mod cli;
+pub mod context;
+pub mod delta;
+pub mod diff;
+pub mod encoding;
+pub mod provider;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod table;
+pub mod tui;
+pub mod utils;
+
+use crate::cli::Format;
+use crate::table::TableContext;
+
+/// Register the table at `uri` as `tbl` and run `sql` against it, returning all batches.
+///
+/// A fresh context is created on each call: nothing (object stores, schema inference,
+/// delta log) is shared between calls. Use [`TableContext`] directly to run several
+/// queries against the same table.
+pub async fn query(uri: &str, format: Format, sql: &str) -> Result<Vec<RecordBatch>> {
+    let tblctx = TableContext::new(uri, &None, format)?;
+    tblctx.register_table().await?;
+    Ok(tblctx.context().sql(sql).await?.collect().await?)
+}
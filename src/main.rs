@@ -1,28 +1,49 @@
 use std::fs;
-use std::io::{BufRead, BufReader};
+use std::future::Future;
+use std::io::{self, BufRead, BufReader, IsTerminal};
+use std::process::ExitCode;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
+use anyhow::{anyhow, bail, Context, Result};
+use arrow::array::AsArray;
+use arrow::datatypes::{Int64Type, Schema};
+use arrow::ipc::writer::{FileWriter, StreamWriter};
+use arrow::record_batch::RecordBatch;
 use arrow::util::pretty::pretty_format_batches;
-use clap::Parser;
-use context::SQLContext;
+use clap::{Parser, ValueEnum};
+use datafusion::config::TableParquetOptions;
 use datafusion::dataframe::DataFrameWriteOptions;
-use log::{error, info};
+use datafusion::parquet::arrow::ArrowWriter;
+use datafusion::physical_plan::collect;
+use datafusion::physical_plan::display::DisplayableExecutionPlan;
+use datafusion::physical_plan::displayable;
+use futures::StreamExt;
+use indicatif::ProgressBar;
+use log::info;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
 use simple_logger::SimpleLogger;
 
-mod cli;
-mod context;
-mod table;
-mod tui;
-mod utils;
-
-use crate::cli::{Cli, Commands};
-use crate::table::TableContext;
+use adt::cli::{
+    Cli, Commands, ErrorFormat, Format, OutputFormat, ParquetCompression, PrintFormat, Theme,
+};
+use adt::context::SQLContext;
+#[cfg(feature = "sqlite")]
+use adt::sqlite;
+use adt::table::{parse_expr_list, FileInfo, QueryOptions, TableContext};
+use adt::tui::TuiConfig;
+use adt::utils::Cancelled;
+use adt::{delta, diff, tui, utils};
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     let cli = Cli::parse();
 
+    if cli.error_format == ErrorFormat::Json {
+        std::panic::set_hook(Box::new(utils::json_panic_hook));
+    }
+
     let logger = SimpleLogger::new();
 
     match cli.get_log_level() {
@@ -30,6 +51,29 @@ async fn main() {
         None => {}
     }
 
+    match run(&cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            match cli.error_format {
+                ErrorFormat::Text => eprintln!("error: {:#}", err),
+                ErrorFormat::Json => eprintln!("{}", utils::json_error(&err)),
+            }
+            let interrupted = err.chain().any(|cause| {
+                matches!(
+                    cause.downcast_ref::<Cancelled>(),
+                    Some(Cancelled::Interrupted)
+                )
+            });
+            if interrupted {
+                ExitCode::from(130)
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+    }
+}
+
+async fn run(cli: &Cli) -> Result<()> {
     match &cli.command {
         Commands::View {
             table_path,
@@ -37,78 +81,404 @@ async fn main() {
             query,
             partitions,
             limit,
+            no_limit,
             no_tui,
             output_path,
+            show_types,
+            cache_dir,
+            cache_size_mb,
+            no_pushdown,
+            flatten,
+            explain_pruning,
+            table,
+            explain_schema,
+            compression,
+            filter_file,
+            where_,
+            partition_filter,
+            pick_columns,
+            distinct,
+            retry_on_conflict,
+            sample_files,
+            columns,
+            max_depth,
+            binary_display,
+            max_column_width,
+            projection_pushdown_only,
+            encoding,
+            csv_delimiter,
+            csv_quote,
+            csv_escape,
+            csv_no_header,
+            group_by,
+            agg,
+            order_by,
+            lookup,
+            preserve_order,
+            rename,
+            output_batches_as_arrow_stream,
+            log_store,
+            s3_endpoint,
+            s3_region,
+            count_distinct,
+            exact,
+            to,
+            mode,
+            parquet_compression,
+            sample_head_tail,
+            version_as_of,
+            timestamp_as_of,
+            output,
+            json_array,
+            stats,
         } => {
-            let tblctx = Arc::new(TableContext::new(
-                table_path.as_str(),
-                partitions,
-                format.clone(),
-            ));
+            let limit = if *no_limit { &0 } else { limit };
+            if *limit == 0 && !no_tui && *output == PrintFormat::Table {
+                eprintln!(
+                    "warning: without limit, going to the bottom of the TUI buffers the whole result in memory"
+                );
+            }
+            let mut query_options = QueryOptions {
+                columns: split_list(columns),
+                filters: [read_filter_file(filter_file)?, where_.clone()].concat(),
+                distinct: *distinct,
+                group_by: split_list(group_by),
+                aggregates: agg
+                    .as_deref()
+                    .map(parse_expr_list)
+                    .transpose()
+                    .context("Invalid aggregate expression")?
+                    .unwrap_or_default(),
+                order_by: order_by.clone(),
+                renames: rename
+                    .iter()
+                    .map(|r| parse_rename(r))
+                    .collect::<Result<_>>()?,
+            };
+            let tblctx = Arc::new(
+                TableContext::new(table_path.as_str(), partitions, format.clone())?
+                    .with_cache(cache_dir, *cache_size_mb)
+                    .with_flatten(*flatten)
+                    .with_compression(compression)
+                    .with_load_retries(*retry_on_conflict)
+                    .with_sample_files(*sample_files)
+                    .with_version(*version_as_of)
+                    .with_timestamp(*timestamp_as_of)
+                    .with_projection_pushdown_only(*projection_pushdown_only)
+                    .with_log_store(log_store)
+                    .with_s3_options(s3_endpoint, s3_region)
+                    .with_encoding(encoding)
+                    .context("Unable to convert input encoding")?
+                    .with_csv_options(*csv_delimiter, *csv_quote, *csv_escape, !csv_no_header)
+                    .context("Invalid csv options")?
+                    .with_config(
+                        "datafusion.execution.parquet.pushdown_filters",
+                        &(!no_pushdown).to_string(),
+                    )
+                    .context("Invalid datafusion config")?
+                    .with_preserve_order(*preserve_order)
+                    .context("Invalid datafusion config")?,
+            );
+            if let Some(column) = count_distinct {
+                query_options.aggregates.push(if *exact {
+                    format!("count(distinct \"{}\")", column)
+                } else {
+                    format!("approx_distinct(\"{}\")", column)
+                });
+            }
+            if let Some(spec) = partition_filter {
+                query_options.filters.push(
+                    tblctx
+                        .partition_filter(spec)
+                        .context("Invalid partition filter")?,
+                );
+            }
+            // stderr spinner, kept off non interactive and machine readable output
+            let progress = !no_tui && *output == PrintFormat::Table;
             let req_time = Instant::now();
-            tblctx
-                .register_table()
+            with_spinner(progress, "registering table", tblctx.register_table())
                 .await
-                .expect("Table registration fails");
+                .context("Table registration fails")?;
+            for spec in lookup {
+                tblctx
+                    .register_lookup(spec)
+                    .await
+                    .context("Lookup table registration fails")?;
+            }
             let req_time_elapsed = req_time.elapsed();
             info!("Table registration time: {:.2?}", req_time_elapsed);
+            if *explain_schema {
+                println!(
+                    "Resolved schema:\n{}",
+                    tblctx
+                        .explain_schema()
+                        .await
+                        .context("Unable to resolve schema")?
+                );
+            }
+            let query = if *pick_columns {
+                let columns = tblctx
+                    .column_names()
+                    .await
+                    .context("Unable to get table columns")?;
+                let chosen = tui::pick_columns(&columns)
+                    .map_err(|e| anyhow!("Column picker fails: {}", e))?;
+                match chosen {
+                    Some(chosen) => format!(
+                        "select {} from tbl",
+                        chosen
+                            .iter()
+                            .map(|c| format!("\"{}\"", c))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                    None => return Ok(()),
+                }
+            } else {
+                query.clone()
+            };
             let req_time = Instant::now();
-            let df = tblctx
-                .exec_query(query.clone(), limit.clone())
-                .await
-                .expect("Query execution fails");
-            let records = df
-                .clone()
-                .collect()
+            if *output_batches_as_arrow_stream {
+                run_cancellable(cli.timeout_secs, async {
+                    let mut stream = tblctx
+                        .exec_query(query, limit.clone(), &query_options)
+                        .await
+                        .context("Query execution fails")?
+                        .execute_stream()
+                        .await
+                        .context("Query execution fails")?;
+                    let mut writer = StreamWriter::try_new(io::stdout(), stream.schema().as_ref())
+                        .context("Unable to create arrow stream writer")?;
+                    while let Some(batch) = stream.next().await {
+                        writer
+                            .write(&batch.context("Unable to read record batch")?)
+                            .context("Unable to write arrow stream")?;
+                    }
+                    writer.finish().context("Unable to write arrow stream")
+                })
+                .await?;
+                info!("Query execution time: {:.2?}", req_time.elapsed());
+                return Ok(());
+            }
+            if *sample_head_tail {
+                let (records, separator) = run_cancellable(cli.timeout_secs, async {
+                    tblctx
+                        .exec_head_tail(query, limit.clone(), &query_options)
+                        .await
+                        .context("Query execution fails")
+                })
+                .await?;
+                info!("Query execution time: {:.2?}", req_time.elapsed());
+                let table = pretty_format_batches(&records)
+                    .context("Pretty format fails")?
+                    .to_string();
+                let table = match separator {
+                    Some(rows) => utils::with_row_separator(&table, rows),
+                    None => table,
+                };
+                display(&table, *no_tui, cli.theme)?;
+                return Ok(());
+            }
+            // the TUI fetches rows as they are scrolled to, unless the whole result is needed
+            let stream_to_tui = progress
+                && output_path.is_none()
+                && !explain_pruning
+                && !stats
+                && !show_types
+                && max_depth.is_none();
+            if stream_to_tui {
+                let stream = run_cancellable(cli.timeout_secs, async {
+                    with_spinner(progress, "running query", async {
+                        tblctx
+                            .exec_query(query, limit.clone(), &query_options)
+                            .await
+                            .context("Query execution fails")?
+                            .execute_stream()
+                            .await
+                            .context("Query execution fails")
+                    })
+                    .await
+                })
+                .await?;
+                let config = TuiConfig::load(cli.theme)?
+                    .with_binary_display(*binary_display)
+                    .with_max_column_width(*max_column_width)
+                    .with_timeout_secs(cli.timeout_secs);
+                tui::show_in_tui(stream, &export_records, &config).map_err(|e| {
+                    // keep cancellations typed for the exit code and json errors
+                    match e.downcast::<Cancelled>() {
+                        Ok(cancelled) => anyhow::Error::from(*cancelled),
+                        Err(e) => anyhow!("TUI fails: {}", e),
+                    }
+                })?;
+                return Ok(());
+            }
+            let (df, plan, records) = run_cancellable(cli.timeout_secs, async {
+                with_spinner(progress, "running query", async {
+                    let df = tblctx
+                        .exec_query(query, limit.clone(), &query_options)
+                        .await
+                        .context("Query execution fails")?;
+                    // keep the physical plan around to read its metrics after execution
+                    let plan = df
+                        .clone()
+                        .create_physical_plan()
+                        .await
+                        .context("Unable to create physical plan")?;
+                    let records = collect(plan.clone(), Arc::new(df.task_ctx()))
+                        .await
+                        .context("Unable to collect dataframe records")?;
+                    Ok::<_, anyhow::Error>((df, plan, records))
+                })
                 .await
-                .expect("Unable to collect dataframe records");
+            })
+            .await?;
             let req_time_elapsed = req_time.elapsed();
             info!("Query execution time: {:.2?}", req_time_elapsed);
-            if *no_tui {
-                println!(
-                    "{}",
-                    pretty_format_batches(&records).expect("Pretty format fails")
-                );
+            let result_stats = format!(
+                "{} rows in {} batches, {} in memory",
+                records.iter().map(|b| b.num_rows()).sum::<usize>(),
+                records.len(),
+                utils::human_bytes(
+                    records
+                        .iter()
+                        .map(|b| b.get_array_memory_size() as u64)
+                        .sum()
+                )
+            );
+            info!("Query result: {}", result_stats);
+            if *stats {
+                eprintln!("{}", result_stats);
+            }
+            if *output == PrintFormat::Table {
+                let display_records = if *show_types {
+                    utils::with_types_in_header(Arc::new(Schema::from(df.schema())), &records)
+                        .context("Unable to annotate column types")?
+                } else {
+                    records.clone()
+                };
+                let display_records = match max_depth {
+                    Some(depth) => utils::limit_nested_depth(&display_records, *depth)
+                        .context("Unable to render nested values")?,
+                    None => display_records,
+                };
+                let display_records = utils::display_binary(&display_records, *binary_display)
+                    .context("Unable to render binary values")?;
+                let display_records = match max_column_width {
+                    Some(width) => utils::truncate_strings(&display_records, *width)
+                        .context("Unable to truncate string values")?,
+                    None => display_records,
+                };
+                display(
+                    &pretty_format_batches(&display_records)?.to_string(),
+                    *no_tui,
+                    cli.theme,
+                )?;
+            } else if *output == PrintFormat::Json && *json_array {
+                utils::write_json_array(&records, io::stdout().lock())
+                    .context("Unable to print records")?;
             } else {
-                let _ = tui::show_in_tui(
-                    pretty_format_batches(&records)
-                        .unwrap()
-                        .to_string()
-                        .as_str(),
-                );
+                utils::write_records(&records, *output, io::stdout().lock())
+                    .context("Unable to print records")?;
+            }
+            if *explain_pruning {
+                println!("Pruning summary:\n{}", tblctx.pruning_summary(&plan));
             }
             if let Some(op) = output_path {
-                let ext = std::path::Path::new(op)
-                    .extension()
-                    .expect("Unable to extract file extension")
-                    .to_str();
-                match ext {
-                    Some("csv") => {
-                        info!("export to csv");
-                        let _ = df
-                            .write_csv(
+                let ext = match to {
+                    Some(to) => to.to_possible_value().map(|v| v.get_name().to_string()),
+                    None => std::path::Path::new(op)
+                        .extension()
+                        .and_then(|e| e.to_str())
+                        .map(String::from),
+                };
+                with_spinner(progress, "exporting", async {
+                    match ext.as_deref() {
+                        Some("csv") => {
+                            info!("export to csv");
+                            df.write_csv(
                                 op,
                                 DataFrameWriteOptions::default().with_single_file_output(true),
                                 None,
                             )
                             .await
-                            .unwrap();
-                        {}
-                    }
-                    Some("json") => {
-                        info!("export to newline delimited json");
-                        let _ = df
-                            .write_json(
+                            .context("Csv export fails")?;
+                        }
+                        Some("json") if *json_array => {
+                            info!("export to json array");
+                            let file =
+                                fs::File::create(op).context("Unable to create json file")?;
+                            utils::write_json_array(&records, io::BufWriter::new(file))
+                                .context("Json export fails")?;
+                        }
+                        Some("json") => {
+                            info!("export to newline delimited json");
+                            df.write_json(
                                 op,
                                 DataFrameWriteOptions::default().with_single_file_output(true),
                                 None,
                             )
                             .await
-                            .unwrap();
-                        {}
+                            .context("Json export fails")?;
+                        }
+                        Some("parquet") => {
+                            info!("export to parquet");
+                            let mut options = TableParquetOptions::default();
+                            if let Some(compression) = parquet_compression {
+                                let codec = match compression {
+                                    ParquetCompression::Snappy => "snappy",
+                                    ParquetCompression::Gzip => "gzip(6)",
+                                    ParquetCompression::Zstd => "zstd(3)",
+                                };
+                                options.global.compression = Some(codec.to_string());
+                            }
+                            df.write_parquet(
+                                op,
+                                DataFrameWriteOptions::default().with_single_file_output(true),
+                                Some(options),
+                            )
+                            .await
+                            .context("Parquet export fails")?;
+                        }
+                        Some("arrow" | "feather") => {
+                            info!("export to arrow ipc file");
+                            // batches carry the exact (nested) arrow schema of the result
+                            let schema = records
+                                .first()
+                                .map(|b| b.schema().as_ref().clone())
+                                .unwrap_or_else(|| Schema::from(df.schema()));
+                            let file =
+                                fs::File::create(op).context("Unable to create arrow file")?;
+                            let mut writer = FileWriter::try_new(file, &schema)
+                                .context("Unable to create arrow file writer")?;
+                            for batch in &records {
+                                writer.write(batch).context("Arrow export fails")?;
+                            }
+                            writer.finish().context("Arrow export fails")?;
+                        }
+                        #[cfg(feature = "sqlite")]
+                        Some("db" | "sqlite") => {
+                            info!("export to sqlite table {}", table);
+                            sqlite::write_sqlite(op, table, &Schema::from(df.schema()), &records)
+                                .context("Sqlite export fails")?;
+                        }
+                        #[cfg(not(feature = "sqlite"))]
+                        Some("db" | "sqlite") => {
+                            let _ = table;
+                            bail!("Sqlite export requires the sqlite feature")
+                        }
+                        Some("delta") => {
+                            info!("export to delta table");
+                            let version = delta::write_delta(op, records, *mode, log_store)
+                                .await
+                                .context("Delta export fails")?;
+                            info!("committed delta table version {}", version);
+                        }
+                        _ => bail!("Unsupported output format"),
                     }
-                    _ => error!("Unsupported output format"),
-                }
+                    Ok::<_, anyhow::Error>(())
+                })
+                .await?;
             }
         }
         Commands::Schema {
@@ -116,42 +486,85 @@ async fn main() {
             partitions,
             format,
             no_tui,
+            explain_schema,
+            compression,
+            version_as_of,
+            timestamp_as_of,
+            retry_on_conflict,
+            sample_files,
+            encoding,
+            csv_delimiter,
+            csv_quote,
+            csv_escape,
+            csv_no_header,
+            log_store,
+            s3_endpoint,
+            s3_region,
+            metadata,
+            with_stats,
         } => {
-            let tblctx = Arc::new(TableContext::new(
-                table_path.as_str(),
-                partitions,
-                format.clone(),
-            ));
+            let tblctx = Arc::new(
+                TableContext::new(table_path.as_str(), partitions, format.clone())?
+                    .with_compression(compression)
+                    .with_version(*version_as_of)
+                    .with_timestamp(*timestamp_as_of)
+                    .with_load_retries(*retry_on_conflict)
+                    .with_sample_files(*sample_files)
+                    .with_log_store(log_store)
+                    .with_s3_options(s3_endpoint, s3_region)
+                    .with_encoding(encoding)
+                    .context("Unable to convert input encoding")?
+                    .with_csv_options(*csv_delimiter, *csv_quote, *csv_escape, !csv_no_header)
+                    .context("Invalid csv options")?,
+            );
             let req_time = Instant::now();
             tblctx
                 .register_table()
                 .await
-                .expect("Table registration fails");
+                .context("Table registration fails")?;
             let req_time_elapsed = req_time.elapsed();
             info!("Table registration time: {:.2?}", req_time_elapsed);
+            if *explain_schema {
+                println!(
+                    "Resolved schema:\n{}",
+                    tblctx
+                        .explain_schema()
+                        .await
+                        .context("Unable to resolve schema")?
+                );
+            }
             let req_time = Instant::now();
-            let records = tblctx
-                .schema()
-                .await
-                .expect("Schema query fails")
-                .collect()
-                .await
-                .expect("Schema collect fails");
+            let records = run_cancellable(cli.timeout_secs, async {
+                if *with_stats {
+                    let stats = tblctx
+                        .column_stats()
+                        .await
+                        .context("Column statistics read fails")?;
+                    return Ok(vec![stats]);
+                }
+                tblctx
+                    .schema()
+                    .await
+                    .context("Schema query fails")?
+                    .collect()
+                    .await
+                    .context("Schema collect fails")
+            })
+            .await?;
             let req_time_elapsed = req_time.elapsed();
             info!("Query execution time: {:.2?}", req_time_elapsed);
-            if *no_tui {
-                println!(
-                    "{}",
-                    pretty_format_batches(&records).expect("Pretty format fails")
-                );
-            } else {
-                let _ = tui::show_in_tui(
-                    pretty_format_batches(&records)
-                        .unwrap()
-                        .to_string()
-                        .as_str(),
-                );
+            let mut text = pretty_format_batches(&records)?.to_string();
+            if let Some(columns) = tblctx.delta_partition_columns().filter(|c| !c.is_empty()) {
+                text = format!("{}\npartition columns: {}", text, columns.join(", "));
             }
+            if *metadata {
+                let metadata = tblctx
+                    .delta_metadata()
+                    .await
+                    .context("Unable to read table metadata")?;
+                text = format!("{}\n{}", text, pretty_format_batches(&[metadata])?);
+            }
+            display(&text, *no_tui, cli.theme)?;
         }
         Commands::Explain {
             table_path,
@@ -159,32 +572,220 @@ async fn main() {
             query,
             limit,
             partitions,
+            no_pushdown,
+            filter_file,
+            where_,
+            analyze,
         } => {
             // Create table context
-            let tblctx = Arc::new(TableContext::new(
-                table_path.as_str(),
-                partitions,
-                format.clone(),
-            ));
+            let tblctx = Arc::new(
+                TableContext::new(table_path.as_str(), partitions, format.clone())?
+                    .with_config(
+                        "datafusion.execution.parquet.pushdown_filters",
+                        &(!no_pushdown).to_string(),
+                    )
+                    .context("Invalid datafusion config")?,
+            );
             tblctx
                 .register_table()
                 .await
-                .expect("Table registration fails");
+                .context("Table registration fails")?;
             // parse the SQL
-            let full_query = tblctx.build_query(query.clone(), limit.clone());
+            let query_options = QueryOptions {
+                filters: [read_filter_file(filter_file)?, where_.clone()].concat(),
+                ..Default::default()
+            };
+            let full_query = tblctx.build_query(query.clone(), limit.clone(), &query_options);
             let initial_plan = tblctx
                 .context()
                 .state()
                 .create_logical_plan(full_query.as_ref())
                 .await
-                .unwrap();
+                .context("Invalid query")?;
             // show the plan
             println!("Initial Plan:\n{:?}", initial_plan.clone());
 
-            let optimized_plan = tblctx.context().state().optimize(&initial_plan);
+            let optimized_plan = tblctx
+                .context()
+                .state()
+                .optimize(&initial_plan)
+                .context("Query optimization fails")?;
 
             // show the plan
-            println!("Optimized Plan:\n{:?}", optimized_plan.unwrap());
+            println!("Optimized Plan:\n{:?}", optimized_plan);
+
+            // planned only, scans show their pushed down predicates and pruning
+            let physical_plan = tblctx
+                .context()
+                .state()
+                .create_physical_plan(&optimized_plan)
+                .await
+                .context("Unable to create physical plan")?;
+            println!(
+                "Physical Plan:\n{}",
+                displayable(physical_plan.as_ref()).indent(true)
+            );
+
+            if *analyze {
+                let task_ctx = tblctx.context().task_ctx();
+                run_cancellable(cli.timeout_secs, async {
+                    collect(physical_plan.clone(), task_ctx)
+                        .await
+                        .context("Query execution fails")
+                })
+                .await?;
+                println!(
+                    "Analyzed Plan:\n{}",
+                    DisplayableExecutionPlan::with_metrics(physical_plan.as_ref()).indent(true)
+                );
+            }
+        }
+        Commands::Size {
+            table_path,
+            format,
+            partitions,
+        } => {
+            let tblctx = TableContext::new(table_path.as_str(), partitions, format.clone())?;
+            tblctx
+                .register_table()
+                .await
+                .context("Table registration fails")?;
+            let (files, bytes) = tblctx.size().await.context("Size computation fails")?;
+            println!("files: {}", files);
+            println!("size: {} ({} bytes)", utils::human_bytes(bytes), bytes);
+        }
+        Commands::DeletionVectors { table_path, no_tui } => {
+            let tblctx = TableContext::new(table_path.as_str(), &None, Format::Delta)?;
+            tblctx
+                .register_table()
+                .await
+                .context("Table registration fails")?;
+            let summary = tblctx
+                .deletion_vector_summary()
+                .await
+                .context("Deletion vector summary fails")?;
+            let deleted_rows: i64 = summary
+                .column_by_name("deleted_rows")
+                .map(|c| c.as_primitive::<Int64Type>().iter().flatten().sum())
+                .unwrap_or(0);
+            display(
+                &pretty_format_batches(&[summary])?.to_string(),
+                *no_tui,
+                cli.theme,
+            )?;
+            println!("total deleted rows: {}", deleted_rows);
+        }
+        Commands::Stats { table_path, no_tui } => {
+            let tblctx = TableContext::new(table_path.as_str(), &None, Format::Delta)?;
+            tblctx
+                .register_table()
+                .await
+                .context("Table registration fails")?;
+            let stats = tblctx
+                .table_stats()
+                .await
+                .context("Table stats computation fails")?
+                .to_record_batch()
+                .context("Table stats computation fails")?;
+            display(
+                &pretty_format_batches(&[stats])?.to_string(),
+                *no_tui,
+                cli.theme,
+            )?;
+        }
+        Commands::History {
+            table_path,
+            format,
+            limit,
+            no_tui,
+        } => {
+            let tblctx = TableContext::new(table_path.as_str(), &None, format.clone())?;
+            if tblctx.format().await? != Format::Delta {
+                bail!("history is only supported for delta tables");
+            }
+            tblctx
+                .register_table()
+                .await
+                .context("Table registration fails")?;
+            let history = tblctx.history(*limit).await.context("History read fails")?;
+            display(
+                &pretty_format_batches(&[history])?.to_string(),
+                *no_tui,
+                cli.theme,
+            )?;
+        }
+        Commands::Vacuum {
+            table_path,
+            retention_hours,
+            dry_run,
+            no_tui,
+        } => {
+            if !dry_run {
+                bail!("vacuum only lists removable files for now, pass --dry-run");
+            }
+            let tblctx = TableContext::new(table_path.as_str(), &None, Format::Delta)?;
+            tblctx
+                .register_table()
+                .await
+                .context("Table registration fails")?;
+            let files = tblctx
+                .vacuum_candidates(Duration::from_secs(retention_hours * 3600))
+                .await
+                .context("Unable to read table tombstones")?;
+            let files = FileInfo::to_record_batch(&files).context("Unable to list files")?;
+            display(
+                &pretty_format_batches(&[files])?.to_string(),
+                *no_tui,
+                cli.theme,
+            )?;
+        }
+        Commands::Diff {
+            left_path,
+            right_path,
+            format,
+            key,
+            null_equals_null,
+            limit,
+            partitions,
+            no_tui,
+        } => {
+            let left = TableContext::new(left_path.as_str(), partitions, format.clone())?;
+            let right = TableContext::new(right_path.as_str(), partitions, format.clone())?
+                .with_context(left.context().clone());
+            let req_time = Instant::now();
+            left.register_table_as("left_tbl")
+                .await
+                .context("Left table registration fails")?;
+            right
+                .register_table_as("right_tbl")
+                .await
+                .context("Right table registration fails")?;
+            let req_time_elapsed = req_time.elapsed();
+            info!("Table registration time: {:.2?}", req_time_elapsed);
+            let keys = split_list(key);
+            let diff_query = diff::build_diff_query(left.context(), &keys, *null_equals_null)
+                .await
+                .context("Unable to build diff query")?;
+            let req_time = Instant::now();
+            let records = run_cancellable(cli.timeout_secs, async {
+                left.exec_query(diff_query, limit.clone(), &QueryOptions::default())
+                    .await
+                    .context("Query execution fails")?
+                    .collect()
+                    .await
+                    .context("Unable to collect dataframe records")
+            })
+            .await?;
+            let req_time_elapsed = req_time.elapsed();
+            info!("Query execution time: {:.2?}", req_time_elapsed);
+            display(
+                &pretty_format_batches(&records)?.to_string(),
+                *no_tui,
+                cli.theme,
+            )?;
+        }
+        Commands::VersionInfo => {
+            println!("{}", utils::version_info());
         }
         // Commands::Execute { sql_file } => {
         //     let cfg = RuntimeConfig::new();
@@ -243,11 +844,14 @@ async fn main() {
         //         );
         //     }
         // }
-        Commands::Execute { sql_file } => {
-            let ctx = SQLContext::new();
+        Commands::Execute {
+            sql_file,
+            cache_dir,
+        } => {
+            let ctx = SQLContext::new()?.with_schema_cache(cache_dir);
             let mut query = "".to_owned();
-            let file = fs::File::open(sql_file);
-            let reader = BufReader::new(file.unwrap());
+            let file = fs::File::open(sql_file).context("Unable to open sql file")?;
+            let reader = BufReader::new(file);
             for line in reader.lines() {
                 match line {
                     Ok(line) if line.starts_with("--") => {
@@ -257,16 +861,7 @@ async fn main() {
                         let line = line.trim_end();
                         query.push_str(line);
                         if line.ends_with(';') {
-                            let df = ctx.sql(&query).await.expect("Query execution fails");
-                            let records = df
-                                .clone()
-                                .collect()
-                                .await
-                                .expect("Unable to collect dataframe records");
-                            println!(
-                                "{}",
-                                pretty_format_batches(&records).expect("Pretty format fails")
-                            );
+                            execute(&ctx, &query, cli.timeout_secs).await?;
                             query = "".to_string();
                         } else {
                             query.push('\n');
@@ -281,17 +876,201 @@ async fn main() {
             // run the left over query if the last statement doesn't contain ‘;’
             // ignore if it only consists of '\n'
             if query.contains(|c| c != '\n') {
-                let df = ctx.sql(&query).await.expect("Query execution fails");
-                let records = df
-                    .clone()
-                    .collect()
-                    .await
-                    .expect("Unable to collect dataframe records");
-                println!(
-                    "{}",
-                    pretty_format_batches(&records).expect("Pretty format fails")
-                );
+                execute(&ctx, &query, cli.timeout_secs).await?;
+            }
+        }
+        Commands::Repl => {
+            let ctx = SQLContext::new()?;
+            let mut rl = DefaultEditor::new().context("Unable to open the prompt")?;
+            let history = std::env::var("HOME")
+                .ok()
+                .map(|home| format!("{}/.adt_history", home));
+            if let Some(history) = &history {
+                // missing on first run
+                let _ = rl.load_history(history);
+            }
+            let mut query = String::new();
+            loop {
+                let prompt = if query.is_empty() { "adt> " } else { "...> " };
+                let line = match rl.readline(prompt) {
+                    Ok(line) => line,
+                    // Ctrl-C discards the statement being typed
+                    Err(ReadlineError::Interrupted) => {
+                        query.clear();
+                        continue;
+                    }
+                    Err(ReadlineError::Eof) => break,
+                    Err(err) => return Err(err).context("Unable to read the prompt"),
+                };
+                let line = line.trim_end();
+                if query.is_empty() && line.starts_with('\\') {
+                    let _ = rl.add_history_entry(line);
+                    let statement = match meta_command(line) {
+                        Ok(Some(statement)) => statement,
+                        Ok(None) => break,
+                        Err(err) => {
+                            eprintln!("error: {:#}", err);
+                            continue;
+                        }
+                    };
+                    if let Err(err) = execute(&ctx, &statement, cli.timeout_secs).await {
+                        eprintln!("error: {:#}", err);
+                    }
+                    continue;
+                }
+                if line.is_empty() || line.starts_with("--") {
+                    continue;
+                }
+                query.push_str(line);
+                if line.ends_with(';') {
+                    let _ = rl.add_history_entry(query.as_str());
+                    if let Err(err) = execute(&ctx, &query, cli.timeout_secs).await {
+                        eprintln!("error: {:#}", err);
+                    }
+                    query.clear();
+                } else {
+                    query.push('\n');
+                }
+            }
+            if let Some(history) = &history {
+                let _ = rl.save_history(history);
             }
         }
     }
+    Ok(())
+}
+
+/// Run a sql statement and print its result
+async fn execute(ctx: &SQLContext, query: &str, timeout_secs: Option<u64>) -> Result<()> {
+    let records = run_cancellable(timeout_secs, async {
+        ctx.sql(query)
+            .await
+            .context("Query execution fails")?
+            .collect()
+            .await
+            .context("Unable to collect dataframe records")
+    })
+    .await?;
+    // DDL statements, e.g. create external table, return no rows
+    if !records.is_empty() {
+        println!(
+            "{}",
+            pretty_format_batches(&records).context("Pretty format fails")?
+        );
+    }
+    Ok(())
+}
+
+/// Export the rows fetched by the TUI to `path`
+fn export_records(path: &str, fmt: OutputFormat, records: &[RecordBatch]) -> Result<()> {
+    let file = io::BufWriter::new(fs::File::create(path).context("Unable to create export file")?);
+    match fmt {
+        OutputFormat::Csv => utils::write_records(records, PrintFormat::Csv, file)?,
+        OutputFormat::Json => utils::write_records(records, PrintFormat::Json, file)?,
+        OutputFormat::Parquet => {
+            let schema = match records.first() {
+                Some(batch) => batch.schema(),
+                None => bail!("No rows to export"),
+            };
+            let mut writer = ArrowWriter::try_new(file, schema, None)?;
+            for batch in records {
+                writer.write(batch)?;
+            }
+            writer.close()?;
+        }
+        _ => bail!("Unsupported export format"),
+    }
+    Ok(())
+}
+
+/// Statement run for a repl meta command, `None` to quit
+fn meta_command(line: &str) -> Result<Option<String>> {
+    let mut args = line.split_whitespace();
+    match (args.next(), args.next()) {
+        (Some("\\q"), None) => Ok(None),
+        (Some("\\dt"), None) => Ok(Some(
+            "select table_catalog, table_schema, table_name, table_type \
+             from information_schema.tables where table_schema <> 'information_schema'"
+                .to_string(),
+        )),
+        (Some("\\d"), Some(table)) => Ok(Some(format!("show columns from {}", table))),
+        _ => bail!(
+            "unknown command {}, expected \\dt, \\d <table> or \\q",
+            line
+        ),
+    }
+}
+
+/// Print `text`, or show it in the TUI with the given `theme` unless `no_tui` is set
+fn display(text: &str, no_tui: bool, theme: Option<Theme>) -> Result<()> {
+    if no_tui {
+        println!("{}", text);
+    } else {
+        let config = TuiConfig::load(theme)?;
+        tui::show_text_in_tui(text, &config).map_err(|e| anyhow!("TUI fails: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Show `message` with a spinner on stderr while `fut` runs, when `enabled` and stderr is
+/// a terminal
+async fn with_spinner<T>(enabled: bool, message: &str, fut: impl Future<Output = T>) -> T {
+    if !enabled || !io::stderr().is_terminal() {
+        return fut.await;
+    }
+    let spinner = ProgressBar::new_spinner().with_message(message.to_string());
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    let res = fut.await;
+    spinner.finish_and_clear();
+    res
+}
+
+/// Run `fut`, failing with a `Cancelled` error on Ctrl-C or if it does not complete within
+/// `timeout_secs`
+///
+/// `fut` is dropped on cancellation, which cancels the running datafusion streams.
+async fn run_cancellable<T>(
+    timeout_secs: Option<u64>,
+    fut: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let deadline = async {
+        match timeout_secs {
+            Some(secs) => tokio::time::sleep(Duration::from_secs(secs)).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::select! {
+        res = fut => res,
+        _ = deadline => Err(Cancelled::TimedOut(timeout_secs.unwrap_or_default()).into()),
+        _ = tokio::signal::ctrl_c() => Err(Cancelled::Interrupted.into()),
+    }
+}
+
+/// Split a comma separated cli option into its trimmed items
+fn split_list(list: &Option<String>) -> Vec<String> {
+    list.as_deref()
+        .map(|l| l.split(',').map(|s| s.trim().to_string()).collect())
+        .unwrap_or_default()
+}
+
+/// Parse a `old=new` column rename
+fn parse_rename(rename: &str) -> Result<(String, String)> {
+    let (old, new) = rename
+        .split_once('=')
+        .with_context(|| format!("Invalid rename {}, expected old=new", rename))?;
+    Ok((old.trim().to_string(), new.trim().to_string()))
+}
+
+/// Read the predicate stored in `filter_file`, if any
+fn read_filter_file(filter_file: &Option<String>) -> Result<Vec<String>> {
+    filter_file
+        .iter()
+        .map(|path| {
+            Ok(fs::read_to_string(path)
+                .context("Unable to read filter file")?
+                .trim()
+                .trim_end_matches(';')
+                .to_string())
+        })
+        .collect()
 }
@@ -0,0 +1,150 @@
+use anyhow::Result;
+use arrow::array::{Array, ArrayRef, AsArray};
+use arrow::compute::{cast_with_options, CastOptions};
+use arrow::datatypes::{DataType, Float64Type, Int64Type, Schema};
+use arrow::record_batch::RecordBatch;
+use arrow::util::display::{ArrayFormatter, FormatOptions};
+use rusqlite::types::Value;
+use rusqlite::{params_from_iter, Connection};
+
+/// Write `records` into a new `table` of the sqlite database at `path`
+pub fn write_sqlite(
+    path: &str,
+    table: &str,
+    schema: &Schema,
+    records: &[RecordBatch],
+) -> Result<()> {
+    let mut conn = Connection::open(path)?;
+    let columns: Vec<String> = schema
+        .fields()
+        .iter()
+        .map(|f| format!("\"{}\" {}", f.name(), sqlite_type(f.data_type())))
+        .collect();
+    conn.execute(
+        format!("create table \"{}\" ({})", table, columns.join(", ")).as_str(),
+        [],
+    )?;
+    let placeholders = vec!["?"; columns.len()].join(", ");
+    let tx = conn.transaction()?;
+    {
+        let mut stmt =
+            tx.prepare(format!("insert into \"{}\" values ({})", table, placeholders).as_str())?;
+        for batch in records {
+            let columns = batch
+                .columns()
+                .iter()
+                .map(to_sqlite_array)
+                .collect::<Result<Vec<_>>>()?;
+            for row in 0..batch.num_rows() {
+                let values = columns
+                    .iter()
+                    .map(|c| sqlite_value(c, row))
+                    .collect::<Result<Vec<_>>>()?;
+                stmt.execute(params_from_iter(values))?;
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+fn sqlite_type(data_type: &DataType) -> &'static str {
+    match data_type {
+        DataType::Boolean
+        | DataType::Int8
+        | DataType::Int16
+        | DataType::Int32
+        | DataType::Int64
+        | DataType::UInt8
+        | DataType::UInt16
+        | DataType::UInt32 => "INTEGER",
+        // sqlite integers are signed 64 bits, larger values would be rounded as REAL
+        DataType::UInt64 => "TEXT",
+        DataType::Float16 | DataType::Float32 | DataType::Float64 => "REAL",
+        DataType::Binary | DataType::LargeBinary | DataType::FixedSizeBinary(_) => "BLOB",
+        _ => "TEXT",
+    }
+}
+
+/// Cast an arrow column to the arrow type matching its sqlite storage class
+fn to_sqlite_array(array: &ArrayRef) -> Result<ArrayRef> {
+    let target = match sqlite_type(array.data_type()) {
+        "INTEGER" => DataType::Int64,
+        "REAL" => DataType::Float64,
+        "BLOB" => DataType::LargeBinary,
+        _ => return Ok(array.clone()),
+    };
+    // fail rather than silently storing NULL for values out of the target range
+    let options = CastOptions {
+        safe: false,
+        ..Default::default()
+    };
+    Ok(cast_with_options(array, &target, &options)?)
+}
+
+fn sqlite_value(array: &ArrayRef, row: usize) -> Result<Value> {
+    if array.is_null(row) {
+        return Ok(Value::Null);
+    }
+    let value = match array.data_type() {
+        DataType::Int64 => Value::Integer(array.as_primitive::<Int64Type>().value(row)),
+        DataType::Float64 => Value::Real(array.as_primitive::<Float64Type>().value(row)),
+        DataType::LargeBinary => Value::Blob(array.as_binary::<i64>().value(row).to_vec()),
+        _ => {
+            let formatter = ArrayFormatter::try_new(array.as_ref(), &FormatOptions::default())?;
+            Value::Text(formatter.value(row).to_string())
+        }
+    };
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::{Int32Array, UInt64Array};
+    use std::sync::Arc;
+
+    #[test]
+    fn round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("export.db");
+        let big = i64::MAX as u64 + 1;
+        let batch = RecordBatch::try_from_iter(vec![
+            (
+                "id",
+                Arc::new(Int32Array::from(vec![1, 2, 3, 4])) as ArrayRef,
+            ),
+            (
+                "count",
+                Arc::new(UInt64Array::from(vec![
+                    Some(0),
+                    Some(big),
+                    Some(u64::MAX),
+                    None,
+                ])) as ArrayRef,
+            ),
+        ])
+        .unwrap();
+        let path = path.to_str().unwrap();
+        write_sqlite(path, "tbl", &batch.schema(), &[batch.clone()]).unwrap();
+
+        let conn = Connection::open(path).unwrap();
+        let mut stmt = conn
+            .prepare("select id, count from tbl order by id")
+            .unwrap();
+        let rows: Vec<(i64, Option<String>)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                (1, Some("0".to_string())),
+                (2, Some(big.to_string())),
+                (3, Some(u64::MAX.to_string())),
+                (4, None),
+            ]
+        );
+    }
+}
@@ -1,68 +1,548 @@
-use anyhow::Result;
-use datafusion::arrow::datatypes::DataType;
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use datafusion::arrow::array::{ArrayRef, Int64Array, StringArray, TimestampMillisecondArray};
+use datafusion::arrow::compute::concat_batches;
+use datafusion::arrow::datatypes::{DataType, Field, Schema};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::config::CsvOptions;
+use datafusion::datasource::file_format::avro::AvroFormat;
+use datafusion::datasource::file_format::csv::CsvFormat;
+use datafusion::datasource::file_format::file_compression_type::FileCompressionType;
+use datafusion::datasource::file_format::json::JsonFormat;
 use datafusion::datasource::file_format::parquet::ParquetFormat;
+use datafusion::datasource::file_format::FileFormat;
 use datafusion::datasource::listing::{
     ListingOptions, ListingTable, ListingTableConfig, ListingTableUrl,
 };
+use datafusion::datasource::physical_plan::ParquetExec;
 use datafusion::datasource::TableProvider;
 use datafusion::execution::context::SessionConfig;
+use datafusion::physical_plan::ExecutionPlan;
 use datafusion::prelude::*;
+use datafusion::sql::sqlparser::dialect::GenericDialect;
+use datafusion::sql::sqlparser::parser::Parser;
+use datafusion::sql::sqlparser::tokenizer::Token;
 use deltalake::{DeltaTable, DeltaTableBuilder};
-use log::{debug, info};
-use object_store::aws::AmazonS3Builder;
-use std::sync::Arc;
+use futures::TryStreamExt;
+use log::{debug, info, warn};
+use object_store::azure::MicrosoftAzureBuilder;
+use object_store::http::HttpBuilder;
+use object_store::path::Path;
+use object_store::prefix::PrefixStore;
+use object_store::{ObjectMeta, ObjectStore};
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 use url::Url;
 
-use crate::cli::Format;
-use crate::utils::ensure_scheme;
+use crate::cache::CachedObjectStore;
+use crate::cli::{Compression, Format, LogStore};
+use crate::delta::{self, log_store_options};
+use crate::encoding::{transcode_to_utf8, TranscodedCopy};
+use crate::provider::ProjectionOnlyTable;
+use crate::utils::{
+    compression_from_path, ensure_scheme, gcs_builder, s3_builder, AZURE_STORE_ERROR,
+};
+
+/// Shortcuts composed into the query by `build_query`
+#[derive(Clone, Default)]
+pub struct QueryOptions {
+    /// columns projected from the query, nested fields as dotted paths (`user.id`)
+    pub columns: Vec<String>,
+    /// predicates AND-ed into a WHERE clause over the query
+    pub filters: Vec<String>,
+    /// only keep distinct rows of the (filtered) query
+    pub distinct: bool,
+    /// columns the query is grouped by, projected before the aggregates
+    pub group_by: Vec<String>,
+    /// aggregate expressions computed per group, `count(*)` by default when grouping
+    pub aggregates: Vec<String>,
+    /// ORDER BY clause of the wrapping query, e.g. `count(*) desc`
+    pub order_by: Option<String>,
+    /// `(old, new)` column renames applied to the query result
+    pub renames: Vec<(String, String)>,
+}
+
+impl QueryOptions {
+    fn is_aggregation(&self) -> bool {
+        !self.group_by.is_empty() || !self.aggregates.is_empty()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+            && self.columns.is_empty()
+            && !self.distinct
+            && !self.is_aggregation()
+            && self.order_by.is_none()
+    }
+}
+
+/// Size summary of a delta table snapshot
+pub struct TableStats {
+    pub files: usize,
+    pub bytes: u64,
+    /// total rows, `None` when some files have no statistics
+    pub rows: Option<i64>,
+    /// distinct partition values, 0 for unpartitioned tables
+    pub partitions: usize,
+    /// rows masked by deletion vectors
+    pub deleted_rows: i64,
+}
+
+impl TableStats {
+    /// Single row `files, bytes, rows, partitions, deleted_rows` batch
+    pub fn to_record_batch(&self) -> Result<RecordBatch> {
+        Ok(RecordBatch::try_from_iter(vec![
+            (
+                "files",
+                Arc::new(Int64Array::from(vec![self.files as i64])) as ArrayRef,
+            ),
+            (
+                "bytes",
+                Arc::new(Int64Array::from(vec![self.bytes as i64])) as ArrayRef,
+            ),
+            (
+                "rows",
+                Arc::new(Int64Array::from(vec![self.rows])) as ArrayRef,
+            ),
+            (
+                "partitions",
+                Arc::new(Int64Array::from(vec![self.partitions as i64])) as ArrayRef,
+            ),
+            (
+                "deleted_rows",
+                Arc::new(Int64Array::from(vec![self.deleted_rows])) as ArrayRef,
+            ),
+        ])?)
+    }
+}
+
+/// Data file removed from a delta table, still present in storage until vacuumed
+pub struct FileInfo {
+    pub path: String,
+    /// size in bytes, when recorded by the writer
+    pub size: Option<i64>,
+    /// removal time in milliseconds since the epoch
+    pub deletion_timestamp: Option<i64>,
+}
+
+impl FileInfo {
+    /// `path, size, deleted_at` batch with one row per file
+    pub fn to_record_batch(files: &[FileInfo]) -> Result<RecordBatch> {
+        let paths: StringArray = files.iter().map(|f| Some(f.path.as_str())).collect();
+        let sizes: Int64Array = files.iter().map(|f| f.size).collect();
+        let deleted_at = TimestampMillisecondArray::from(
+            files
+                .iter()
+                .map(|f| f.deletion_timestamp)
+                .collect::<Vec<_>>(),
+        )
+        .with_timezone("UTC");
+        Ok(RecordBatch::try_from_iter(vec![
+            ("path", Arc::new(paths) as ArrayRef),
+            ("size", Arc::new(sizes) as ArrayRef),
+            ("deleted_at", Arc::new(deleted_at) as ArrayRef),
+        ])?)
+    }
+}
 
 pub struct TableContext {
     ctx: SessionContext,
     path: Url,
     partition_spec: Option<Vec<(String, DataType)>>,
     fmt: Format,
+    /// format probed from the table path when created with `Format::Auto`
+    resolved_fmt: OnceLock<Format>,
+    cache_dir: Option<PathBuf>,
+    cache_size: usize,
+    flatten: bool,
+    compression: Option<FileCompressionType>,
+    csv_options: CsvOptions,
+    version: Option<i64>,
+    timestamp: Option<DateTime<Utc>>,
+    load_retries: u32,
+    sample_files: Option<usize>,
+    projection_pushdown_only: bool,
+    transcoded: Option<TranscodedCopy>,
+    storage_options: HashMap<String, String>,
+    active_files: OnceLock<usize>,
+    delta_partitions: OnceLock<Vec<String>>,
+    /// loaded delta table, registered tables may wrap or replace its provider
+    delta_table: OnceLock<DeltaTable>,
 }
 
 impl TableContext {
-    pub fn new(table_path: &str, partitions: &Option<String>, fmt: Format) -> Self {
-        Self {
+    pub fn new(table_path: &str, partitions: &Option<String>, fmt: Format) -> Result<Self> {
+        let path = match ensure_scheme(table_path) {
+            Ok(url) => url,
+            // tell why local paths are unusable, e.g. missing files
+            Err(()) => match std::path::Path::new(table_path).canonicalize() {
+                Err(e) => {
+                    return Err(e).with_context(|| format!("invalid table path {}", table_path))
+                }
+                Ok(_) => bail!("invalid table path {}", table_path),
+            },
+        };
+        Ok(Self {
             ctx: SessionContext::new_with_config(
-                SessionConfig::default().with_information_schema(true),
+                SessionConfig::default()
+                    .with_information_schema(true)
+                    .set_bool("datafusion.execution.parquet.pushdown_filters", true),
             ),
-            path: ensure_scheme(table_path).unwrap(),
-            partition_spec: get_partitions_spec(partitions),
+            path,
+            partition_spec: get_partitions_spec(partitions)?,
             fmt: fmt,
+            resolved_fmt: OnceLock::new(),
+            cache_dir: None,
+            cache_size: 0,
+            flatten: false,
+            compression: None,
+            csv_options: CsvOptions::default().with_has_header(true),
+            version: None,
+            timestamp: None,
+            load_retries: 0,
+            sample_files: None,
+            projection_pushdown_only: false,
+            transcoded: None,
+            storage_options: HashMap::new(),
+            active_files: OnceLock::new(),
+            delta_partitions: OnceLock::new(),
+            delta_table: OnceLock::new(),
+        })
+    }
+
+    /// Cache remote parquet files under `cache_dir`, up to `cache_size_mb` MiB
+    pub fn with_cache(mut self, cache_dir: &Option<String>, cache_size_mb: usize) -> Self {
+        self.cache_dir = cache_dir.as_ref().map(PathBuf::from);
+        self.cache_size = cache_size_mb * 1024 * 1024;
+        self
+    }
+
+    /// Flatten nested struct fields into `parent_child` top level columns
+    pub fn with_flatten(mut self, flatten: bool) -> Self {
+        self.flatten = flatten;
+        self
+    }
+
+    /// Force the compression of json files instead of inferring it from the path
+    pub fn with_compression(mut self, compression: &Option<Compression>) -> Self {
+        self.compression = compression.map(|c| match c {
+            Compression::Uncompressed => FileCompressionType::UNCOMPRESSED,
+            Compression::Gzip => FileCompressionType::GZIP,
+            Compression::Bzip2 => FileCompressionType::BZIP2,
+            Compression::Xz => FileCompressionType::XZ,
+            Compression::Zstd => FileCompressionType::ZSTD,
+        });
+        self
+    }
+
+    /// Load the delta table as of the given version instead of the latest one
+    pub fn with_version(mut self, version: Option<i64>) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Load the delta table version active at the given instant instead of the latest one
+    pub fn with_timestamp(mut self, timestamp: Option<DateTime<Utc>>) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// Retry loading the delta log up to `retries` times, e.g. while the table is being written
+    pub fn with_load_retries(mut self, retries: u32) -> Self {
+        self.load_retries = retries;
+        self
+    }
+
+    /// Infer listing table schemas from the first `files` files (by path) only
+    ///
+    /// Delta schemas are read from the log and never open data files.
+    pub fn with_sample_files(mut self, files: Option<usize>) -> Self {
+        self.sample_files = files;
+        self
+    }
+
+    /// Only push projections to the table scan, predicates being evaluated afterwards
+    /// (debugging aid to measure what file skipping and predicate pushdown save)
+    pub fn with_projection_pushdown_only(mut self, enabled: bool) -> Self {
+        self.projection_pushdown_only = enabled;
+        self
+    }
+
+    /// Parse csv files with another delimiter, quote or escape character, or without header
+    pub fn with_csv_options(
+        mut self,
+        delimiter: Option<char>,
+        quote: Option<char>,
+        escape: Option<char>,
+        has_header: bool,
+    ) -> Result<Self> {
+        let byte = |c: char| {
+            u8::try_from(c).map_err(|_| anyhow!("csv delimiter, quote and escape must be ascii"))
+        };
+        if let Some(delimiter) = delimiter {
+            self.csv_options = self.csv_options.with_delimiter(byte(delimiter)?);
+        }
+        if let Some(quote) = quote {
+            self.csv_options = self.csv_options.with_quote(byte(quote)?);
+        }
+        if let Some(escape) = escape {
+            self.csv_options = self.csv_options.with_escape(Some(byte(escape)?));
         }
+        self.csv_options = self.csv_options.with_has_header(has_header);
+        Ok(self)
+    }
+
+    /// Read uncompressed csv/json files written in another encoding through a UTF-8 copy of
+    /// them, to be called after `with_compression`
+    pub fn with_encoding(mut self, encoding: &Option<String>) -> Result<Self> {
+        if let Some(label) = encoding {
+            let fmt = match self.fmt {
+                Format::Auto if self.path.scheme() == "file" => {
+                    Format::infer_from_uri(self.path.path())?
+                }
+                fmt => fmt,
+            };
+            if !matches!(fmt, Format::Csv | Format::Json) {
+                bail!(
+                    "--encoding only applies to csv and json files, not {:?}",
+                    fmt
+                );
+            }
+            let compressed = match &self.compression {
+                Some(compression) => compression.is_compressed(),
+                None => compression_from_path(self.path.path()).is_compressed(),
+            };
+            if compressed {
+                bail!("--encoding only applies to uncompressed files");
+            }
+            let copy = transcode_to_utf8(&self.path, label)?;
+            self.path = copy.url.clone();
+            self.transcoded = Some(copy);
+        }
+        Ok(self)
+    }
+
+    /// Select the delta log store used for s3 tables, plain s3 without locking by default
+    pub fn with_log_store(mut self, log_store: &Option<LogStore>) -> Self {
+        self.storage_options.extend(log_store_options(log_store));
+        self
+    }
+
+    /// Point s3 tables to another endpoint (e.g. MinIO) or region than the environment ones
+    pub fn with_s3_options(mut self, endpoint: &Option<String>, region: &Option<String>) -> Self {
+        if let Some(endpoint) = endpoint {
+            self.storage_options
+                .insert("endpoint".to_string(), endpoint.clone());
+            if endpoint.starts_with("http://") {
+                self.storage_options
+                    .insert("allow_http".to_string(), "true".to_string());
+            }
+        }
+        if let Some(region) = region {
+            self.storage_options
+                .insert("region".to_string(), region.clone());
+        }
+        self
+    }
+
+    /// Keep rows in file order when the query has no ORDER BY
+    ///
+    /// Files are scanned one after the other on a single partition instead of in parallel,
+    /// which is much slower on large tables. Partitioned delta tables are scanned per
+    /// partition value, so order is only preserved within each partition.
+    pub fn with_preserve_order(self, preserve: bool) -> Result<Self> {
+        if !preserve {
+            return Ok(self);
+        }
+        self.with_config("datafusion.execution.target_partitions", "1")?
+            .with_config("datafusion.optimizer.repartition_file_scans", "false")
+    }
+
+    /// Override a datafusion config option, e.g. `datafusion.execution.parquet.pushdown_filters`
+    pub fn with_config(self, key: &str, value: &str) -> Result<Self> {
+        self.ctx
+            .state_ref()
+            .write()
+            .config_mut()
+            .options_mut()
+            .set(key, value)?;
+        Ok(self)
     }
 
     pub fn context(&self) -> &SessionContext {
         &self.ctx
     }
 
+    /// Share an existing session, e.g. to register several tables in the same context
+    pub fn with_context(mut self, ctx: SessionContext) -> Self {
+        self.ctx = ctx;
+        self
+    }
+
     pub async fn register_table(&self) -> Result<()> {
-        debug!("register table");
-        let provider: Arc<dyn TableProvider> = match self.fmt {
+        self.register_table_as("tbl").await
+    }
+
+    pub async fn register_table_as(&self, name: &str) -> Result<()> {
+        debug!("register table {}", name);
+        let fmt = self.format().await?;
+        if (self.version.is_some() || self.timestamp.is_some()) && fmt != Format::Delta {
+            bail!("table version is only supported for delta tables");
+        }
+        if self.version.is_some() && self.timestamp.is_some() {
+            bail!("table version and timestamp are mutually exclusive");
+        }
+        let provider: Arc<dyn TableProvider> = match fmt {
+            Format::Auto => bail!("unresolved table format"),
             Format::Parquet => {
                 let parquet_table = self.parquet_table_provider().await?;
                 Arc::new(parquet_table)
             }
             Format::Delta => {
+                if self.partition_spec.is_some() {
+                    // partition columns come from the delta log
+                    warn!("--partitions is ignored for delta tables");
+                }
                 let delta_table = self.delta_table_provider().await?;
-                Arc::new(delta_table)
+                delta::table_provider(delta_table)?
+            }
+            Format::Json => {
+                let json_table = self.json_table_provider().await?;
+                Arc::new(json_table)
+            }
+            Format::Csv => {
+                let csv_table = self.csv_table_provider().await?;
+                Arc::new(csv_table)
+            }
+            Format::Avro => {
+                let avro_table = self.avro_table_provider().await?;
+                Arc::new(avro_table)
             }
         };
-        self.ctx.register_table("tbl", provider)?;
+        let provider: Arc<dyn TableProvider> = if self.projection_pushdown_only {
+            Arc::new(ProjectionOnlyTable::new(provider))
+        } else {
+            provider
+        };
+        if self.flatten {
+            // expose nested fields as top level columns through a view over the raw table
+            let raw_name = format!("raw_{}", name);
+            self.ctx
+                .register_table(raw_name.as_str(), provider.clone())?;
+            let projection = flatten_projection(provider.schema().as_ref()).join(", ");
+            let view = self
+                .ctx
+                .sql(format!("select {} from {}", projection, raw_name).as_str())
+                .await?
+                .into_view();
+            self.ctx.register_table(name, view)?;
+        } else {
+            self.ctx.register_table(name, provider)?;
+        }
         Ok(())
     }
 
+    /// Register an extra table described as `name=path[:format]` in the same context
+    ///
+    /// Meant for small lookup tables joined against `tbl`, the format defaults to csv.
+    pub async fn register_lookup(&self, spec: &str) -> Result<()> {
+        let (name, location) = spec
+            .split_once('=')
+            .ok_or_else(|| anyhow!("invalid lookup {}, expected name=path[:format]", spec))?;
+        let (path, fmt) = match location.rsplit_once(':') {
+            Some((path, fmt)) => match Format::from_str(fmt, true) {
+                Ok(fmt) => (path, fmt),
+                Err(_) => (location, Format::Csv),
+            },
+            None => (location, Format::Csv),
+        };
+        if name.is_empty() || name == "tbl" {
+            bail!("invalid lookup table name {:?}", name);
+        }
+        TableContext::new(path, &None, fmt)?
+            .with_context(self.ctx.clone())
+            .register_table_as(name)
+            .await
+    }
+
     pub async fn schema(&self) -> Result<DataFrame> {
         let schema_query = "show columns from tbl";
         info!("schema query: {}", schema_query);
         Ok(self.ctx.sql(schema_query).await?)
     }
 
-    pub fn build_query(&self, query: String, limit: usize) -> String {
-        let full_query = if query.starts_with("SELECT") || query.starts_with("select") {
+    /// Compose `options` into `query` and limit it to `limit` rows, 0 meaning no limit
+    pub fn build_query(&self, query: String, limit: usize, options: &QueryOptions) -> String {
+        let is_select = query.starts_with("SELECT") || query.starts_with("select");
+        let query = if is_select && !options.is_empty() {
+            let projection = if options.is_aggregation() {
+                let aggregates = if options.aggregates.is_empty() {
+                    vec![String::from("count(*)")]
+                } else {
+                    options.aggregates.clone()
+                };
+                options
+                    .group_by
+                    .iter()
+                    .map(|c| format!("\"{}\"", c))
+                    .chain(aggregates)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            } else if options.columns.is_empty() {
+                String::from("*")
+            } else {
+                options
+                    .columns
+                    .iter()
+                    .map(|c| column_expr(c))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            let selection = if options.filters.is_empty() {
+                String::new()
+            } else {
+                let predicate = options
+                    .filters
+                    .iter()
+                    .map(|f| format!("({})", f))
+                    .collect::<Vec<_>>()
+                    .join(" AND ");
+                format!(" WHERE {}", predicate)
+            };
+            let grouping = if options.group_by.is_empty() {
+                String::new()
+            } else {
+                let keys = options
+                    .group_by
+                    .iter()
+                    .map(|c| format!("\"{}\"", c))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!(" GROUP BY {}", keys)
+            };
+            let ordering = match &options.order_by {
+                Some(order_by) => {
+                    let (clause, _) = order_by_clause(order_by, &options.renames);
+                    format!(" ORDER BY {}", clause)
+                }
+                None => String::new(),
+            };
+            format!(
+                "SELECT {}{} FROM ({}){}{}{}",
+                if options.distinct { "DISTINCT " } else { "" },
+                projection,
+                query,
+                selection,
+                grouping,
+                ordering
+            )
+        } else {
+            query
+        };
+        let full_query = if is_select && limit > 0 {
             format!("{} LIMIT {}", query, limit)
         } else {
             query.clone()
@@ -71,41 +551,589 @@ impl TableContext {
         full_query
     }
 
-    pub async fn exec_query(&self, query: String, limit: usize) -> Result<DataFrame> {
-        let full_query = self.build_query(query, limit);
-        Ok(self.ctx.sql(full_query.as_str()).await?)
+    pub async fn exec_query(
+        &self,
+        query: String,
+        limit: usize,
+        options: &QueryOptions,
+    ) -> Result<DataFrame> {
+        let is_select = query.starts_with("SELECT") || query.starts_with("select");
+        if let (true, Some(order_by)) = (is_select, &options.order_by) {
+            // a clear error rather than a plan one on misspelled sort columns, checked
+            // against the projected, aggregated and renamed result
+            let (_, columns) = order_by_clause(order_by, &options.renames);
+            if !columns.is_empty() {
+                let unordered = QueryOptions {
+                    order_by: None,
+                    ..options.clone()
+                };
+                let projected = self
+                    .ctx
+                    .sql(&self.build_query(query.clone(), 0, &unordered));
+                let df = rename_columns(projected.await?, &options.renames)?;
+                if let Some(missing) = columns
+                    .iter()
+                    .find(|c| !df.schema().has_column_with_unqualified_name(c))
+                {
+                    bail!("order by column {} not found in the query result", missing);
+                }
+            }
+        }
+        let full_query = self.build_query(query, limit, options);
+        let df = self.ctx.sql(full_query.as_str()).await?;
+        rename_columns(df, &options.renames)
     }
 
-    async fn parquet_table_provider(&self) -> Result<ListingTable> {
-        debug!("register store");
-        let url = &(self.path);
-        match self.path.scheme() {
-            "s3" | "s3a" => {
-                let s3 = AmazonS3Builder::from_env()
-                    .with_bucket_name(
-                        url.host_str()
-                            .expect("failed to extract host/bucket from path"),
+    /// Collect the first and last `n` rows of the query ordered by `options.order_by`
+    ///
+    /// Returns the head rows followed by the tail ones, with the number of head rows to
+    /// place a separator after. When the query has at most `2 * n` rows, they are all
+    /// returned in order without separator.
+    pub async fn exec_head_tail(
+        &self,
+        query: String,
+        n: usize,
+        options: &QueryOptions,
+    ) -> Result<(Vec<RecordBatch>, Option<usize>)> {
+        let order_by = options
+            .order_by
+            .as_deref()
+            .ok_or_else(|| anyhow!("head/tail sampling requires an order by"))?;
+        if n == 0 {
+            // no limit, every row is shown
+            let rows = self.exec_query(query, 0, options).await?.collect().await?;
+            return Ok((rows, None));
+        }
+        let head = self
+            .exec_query(query.clone(), 2 * n + 1, options)
+            .await?
+            .collect()
+            .await?;
+        let rows: usize = head.iter().map(|b| b.num_rows()).sum();
+        if rows <= 2 * n {
+            return Ok((head, None));
+        }
+        let head = concat_batches(&head[0].schema(), &head)?.slice(0, n);
+        // read the tail in reverse order, then restore the requested one
+        let order_by = order_by_clause(order_by, &options.renames).0;
+        let tail_options = QueryOptions {
+            order_by: Some(reverse_order_by(&order_by)?),
+            ..options.clone()
+        };
+        let tail_query = format!(
+            "SELECT * FROM ({}) ORDER BY {}",
+            self.build_query(query, n, &tail_options),
+            order_by
+        );
+        info!("tail query: {}", tail_query);
+        let tail_df = self.ctx.sql(tail_query.as_str()).await?;
+        let tail = rename_columns(tail_df, &options.renames)?.collect().await?;
+        Ok(([vec![head], tail].concat(), Some(n)))
+    }
+
+    /// Count the active data files of the registered table and their total size in bytes
+    ///
+    /// For delta only the files of the current snapshot are accounted, tombstoned files
+    /// are ignored.
+    pub async fn size(&self) -> Result<(usize, u64)> {
+        let provider = self.ctx.table_provider("tbl").await?;
+        if let Some(delta) = self.delta_table.get() {
+            let adds = delta.snapshot()?.file_actions()?;
+            return Ok((adds.len(), adds.iter().map(|add| add.size as u64).sum()));
+        }
+        if let Some(listing) = provider.as_any().downcast_ref::<ListingTable>() {
+            let (mut files, mut bytes) = (0, 0);
+            for url in listing.table_paths() {
+                let store = self.ctx.runtime_env().object_store(url)?;
+                let objects: Vec<ObjectMeta> = url
+                    .list_all_files(
+                        &self.ctx.state(),
+                        store.as_ref(),
+                        &listing.options().file_extension,
                     )
-                    .build()
-                    .expect("Unable to create S3 object store");
-                let s3_url =
-                    Url::parse(&url[url::Position::BeforeScheme..url::Position::AfterHost])
-                        .expect("Unable to get bucket based S3 url");
-                let _ = self
-                    .ctx
-                    .runtime_env()
-                    .object_store_registry
-                    .register_store(&s3_url, Arc::new(s3));
+                    .await?
+                    .try_collect()
+                    .await?;
+                files += objects.len();
+                bytes += objects.iter().map(|meta| meta.size as u64).sum::<u64>();
             }
-            _ => (),
+            return Ok((files, bytes));
         }
+        bail!("unsupported table provider for size computation")
+    }
+
+    /// Rows masked by deletion vectors, per active file of the registered delta table
+    ///
+    /// Returns a `path, num_records, deleted_rows` batch, `num_records` being null when
+    /// the add action carries no statistics.
+    pub async fn deletion_vector_summary(&self) -> Result<RecordBatch> {
+        let delta = self.registered_delta_table().await?;
+        let adds = delta.snapshot()?.file_actions()?;
+        let paths: StringArray = adds.iter().map(|add| Some(add.path.as_str())).collect();
+        let num_records: Int64Array = adds
+            .iter()
+            .map(|add| add.get_stats().ok().flatten().map(|s| s.num_records))
+            .collect();
+        let deleted_rows: Int64Array = adds
+            .iter()
+            .map(|add| {
+                Some(
+                    add.deletion_vector
+                        .as_ref()
+                        .map(|dv| dv.cardinality)
+                        .unwrap_or(0),
+                )
+            })
+            .collect();
+        Ok(RecordBatch::try_from_iter(vec![
+            ("path", Arc::new(paths) as ArrayRef),
+            ("num_records", Arc::new(num_records) as ArrayRef),
+            ("deleted_rows", Arc::new(deleted_rows) as ArrayRef),
+        ])?)
+    }
+
+    /// Active files, bytes, rows, partitions and deleted rows of the registered delta table
+    ///
+    /// `rows` counts physical rows as recorded in file statistics, rows masked by deletion
+    /// vectors included.
+    pub async fn table_stats(&self) -> Result<TableStats> {
+        let delta = self.registered_delta_table().await?;
+        let adds = delta.snapshot()?.file_actions()?;
+        // rows are only known when every file carries statistics
+        let rows = adds
+            .iter()
+            .map(|add| add.get_stats().ok().flatten().map(|s| s.num_records))
+            .sum::<Option<i64>>();
+        let partitions: BTreeSet<Vec<(String, Option<String>)>> = adds
+            .iter()
+            .filter(|add| !add.partition_values.is_empty())
+            .map(|add| {
+                let mut values: Vec<_> = add.partition_values.clone().into_iter().collect();
+                values.sort();
+                values
+            })
+            .collect();
+        Ok(TableStats {
+            files: adds.len(),
+            bytes: adds.iter().map(|add| add.size as u64).sum(),
+            rows,
+            partitions: partitions.len(),
+            deleted_rows: adds
+                .iter()
+                .filter_map(|add| add.deletion_vector.as_ref())
+                .map(|dv| dv.cardinality)
+                .sum(),
+        })
+    }
+
+    /// Commits of the registered delta table, latest first, up to `limit` of them
+    ///
+    /// Returns a `version, timestamp, operation, operation_metrics` batch, metrics being
+    /// rendered as json. Versions are counted down from the loaded one, assuming every
+    /// commit carries its commit info as delta writers do.
+    pub async fn history(&self, limit: Option<usize>) -> Result<RecordBatch> {
+        if self.format().await? != Format::Delta {
+            bail!("history is only supported for delta tables");
+        }
+        let delta = self.registered_delta_table().await?;
+        let commits = delta.history(limit).await?;
+        let versions: Int64Array = (0..commits.len() as i64)
+            .map(|i| Some(delta.version() - i))
+            .collect();
+        let timestamps = TimestampMillisecondArray::from(
+            commits.iter().map(|c| c.timestamp).collect::<Vec<_>>(),
+        )
+        .with_timezone("UTC");
+        let operations: StringArray = commits.iter().map(|c| c.operation.as_deref()).collect();
+        let metrics: StringArray = commits
+            .iter()
+            .map(|c| c.info.get("operationMetrics").map(|m| m.to_string()))
+            .collect();
+        Ok(RecordBatch::try_from_iter(vec![
+            ("version", Arc::new(versions) as ArrayRef),
+            ("timestamp", Arc::new(timestamps) as ArrayRef),
+            ("operation", Arc::new(operations) as ArrayRef),
+            ("operation_metrics", Arc::new(metrics) as ArrayRef),
+        ])?)
+    }
+
+    /// `key, value` batch of the registered delta table metadata and protocol
+    ///
+    /// Configuration entries are listed as `configuration.<key>`.
+    pub async fn delta_metadata(&self) -> Result<RecordBatch> {
+        let delta = self.registered_delta_table().await?;
+        let snapshot = delta.snapshot()?;
+        let metadata = snapshot.metadata();
+        let protocol = snapshot.protocol();
+        let mut entries: Vec<(String, Option<String>)> = vec![
+            ("id".to_string(), Some(metadata.id.clone())),
+            ("name".to_string(), metadata.name.clone()),
+            ("description".to_string(), metadata.description.clone()),
+            (
+                "partition_columns".to_string(),
+                Some(metadata.partition_columns.join(", ")),
+            ),
+            (
+                "min_reader_version".to_string(),
+                Some(protocol.min_reader_version.to_string()),
+            ),
+            (
+                "min_writer_version".to_string(),
+                Some(protocol.min_writer_version.to_string()),
+            ),
+        ];
+        let mut configuration: Vec<_> = metadata.configuration.iter().collect();
+        configuration.sort();
+        entries.extend(
+            configuration
+                .into_iter()
+                .map(|(key, value)| (format!("configuration.{}", key), value.clone())),
+        );
+        let keys: StringArray = entries.iter().map(|(k, _)| Some(k.as_str())).collect();
+        let values: StringArray = entries.iter().map(|(_, v)| v.as_deref()).collect();
+        Ok(RecordBatch::try_from_iter(vec![
+            ("key", Arc::new(keys) as ArrayRef),
+            ("value", Arc::new(values) as ArrayRef),
+        ])?)
+    }
+
+    /// Tombstoned files of the registered delta table removed more than `retention` ago
+    ///
+    /// These are the files a vacuum would delete, files never referenced by the log are
+    /// not listed. Tombstones without removal time are always candidates.
+    pub async fn vacuum_candidates(&self, retention: Duration) -> Result<Vec<FileInfo>> {
+        let delta = self.registered_delta_table().await?;
+        let cutoff = Utc::now().timestamp_millis() - retention.as_millis() as i64;
+        let tombstones = delta
+            .snapshot()?
+            .all_tombstones(delta.object_store())
+            .await?;
+        Ok(tombstones
+            .filter(|remove| remove.deletion_timestamp.unwrap_or(0) < cutoff)
+            .map(|remove| FileInfo {
+                path: remove.path,
+                size: remove.size,
+                deletion_timestamp: remove.deletion_timestamp,
+            })
+            .collect())
+    }
+
+    async fn registered_delta_table(&self) -> Result<DeltaTable> {
+        match self.delta_table.get() {
+            Some(delta) => Ok(delta.clone()),
+            None => bail!("registered table is not a delta table"),
+        }
+    }
+
+    /// Turn a `col=value,...` partition filter into a predicate on declared partition columns
+    ///
+    /// Listing tables prune partition directories before listing files when such a
+    /// predicate reaches the scan.
+    pub fn partition_filter(&self, spec: &str) -> Result<String> {
+        let is_partition_col = |col: &str| {
+            self.partition_spec
+                .iter()
+                .flatten()
+                .any(|(name, _)| name == col.trim())
+        };
+        let predicates = spec
+            .split(',')
+            .map(|s| s.trim())
+            .map(|s| match s.split_once('=') {
+                Some((col, value)) if is_partition_col(col) => Ok(format!(
+                    "\"{}\" = '{}'",
+                    col.trim(),
+                    value.trim().replace('\'', "''")
+                )),
+                Some((col, _)) => Err(anyhow!("{} is not a declared partition column", col)),
+                None => Err(anyhow!(
+                    "invalid partition filter {}, expected col=value",
+                    s
+                )),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        debug!("partition filter: {}", predicates.join(" AND "));
+        Ok(predicates.join(" AND "))
+    }
+
+    /// Names of the registered table columns
+    pub async fn column_names(&self) -> Result<Vec<String>> {
+        let schema = self.ctx.table_provider("tbl").await?.schema();
+        Ok(schema.fields().iter().map(|f| f.name().clone()).collect())
+    }
+
+    /// `name, type, null_count, min, max` batch of the registered table columns
+    ///
+    /// Statistics come from the table scan without executing it: the delta log for delta
+    /// tables, parquet footers for parquet listing tables. Columns without statistics,
+    /// e.g. of csv or json files, get nulls.
+    pub async fn column_stats(&self) -> Result<RecordBatch> {
+        let provider = self.ctx.table_provider("tbl").await?;
+        let schema = provider.schema();
+        let stats = provider
+            .scan(&self.ctx.state(), None, &[], None)
+            .await?
+            .statistics()?;
+        let fields = schema.fields();
+        let names: StringArray = fields.iter().map(|f| Some(f.name().as_str())).collect();
+        let types: StringArray = fields
+            .iter()
+            .map(|f| Some(f.data_type().to_string()))
+            .collect();
+        let column = |i: usize| stats.column_statistics.get(i);
+        let null_counts: Int64Array = (0..fields.len())
+            .map(|i| column(i).and_then(|c| c.null_count.get_value().map(|n| *n as i64)))
+            .collect();
+        let mins: StringArray = (0..fields.len())
+            .map(|i| column(i).and_then(|c| c.min_value.get_value().map(|v| v.to_string())))
+            .collect();
+        let maxs: StringArray = (0..fields.len())
+            .map(|i| column(i).and_then(|c| c.max_value.get_value().map(|v| v.to_string())))
+            .collect();
+        Ok(RecordBatch::try_from_iter(vec![
+            ("name", Arc::new(names) as ArrayRef),
+            ("type", Arc::new(types) as ArrayRef),
+            ("null_count", Arc::new(null_counts) as ArrayRef),
+            ("min", Arc::new(mins) as ArrayRef),
+            ("max", Arc::new(maxs) as ArrayRef),
+        ])?)
+    }
+
+    /// Partition columns of the registered delta table, read from its metadata
+    pub fn delta_partition_columns(&self) -> Option<&[String]> {
+        self.delta_partitions.get().map(|cols| cols.as_slice())
+    }
+
+    /// Describe the schema resolved for the registered table, flagging partition columns
+    pub async fn explain_schema(&self) -> Result<String> {
+        let schema = self.ctx.table_provider("tbl").await?.schema();
+        let partitions: Vec<String> = match self.delta_partitions.get() {
+            Some(cols) => cols.clone(),
+            None => self
+                .partition_spec
+                .iter()
+                .flatten()
+                .map(|(name, _)| name.clone())
+                .collect(),
+        };
+        let lines: Vec<String> = schema
+            .fields()
+            .iter()
+            .enumerate()
+            .map(|(i, f)| {
+                format!(
+                    "{:>4}: {} {}{}{}",
+                    i,
+                    f.name(),
+                    f.data_type(),
+                    if f.is_nullable() { " nullable" } else { "" },
+                    if partitions.contains(f.name()) {
+                        " [partition]"
+                    } else {
+                        ""
+                    }
+                )
+            })
+            .collect();
+        Ok(lines.join("\n"))
+    }
+
+    /// Summarize files and row groups pruned vs scanned by an executed plan
+    pub fn pruning_summary(&self, plan: &Arc<dyn ExecutionPlan>) -> String {
+        let mut summary = PruningSummary::default();
+        summary.visit(plan.as_ref());
+        let files = match self.active_files.get() {
+            Some(active) => format!(
+                "{} scanned / {} active ({} pruned)",
+                summary.files_scanned,
+                active,
+                active.saturating_sub(summary.files_scanned)
+            ),
+            None => format!("{} scanned", summary.files_scanned),
+        };
+        format!(
+            "files: {}\nrow groups: {} matched, {} pruned by statistics, {} pruned by bloom filter\nrows filtered: {} by pushdown, {} by page index",
+            files,
+            summary.row_groups_matched,
+            summary.row_groups_pruned_statistics,
+            summary.row_groups_pruned_bloom_filter,
+            summary.pushdown_rows_filtered,
+            summary.page_index_rows_filtered,
+        )
+    }
+
+    async fn parquet_table_provider(&self) -> Result<ListingTable> {
         debug!("get parquet table provider");
         let file_format = ParquetFormat::default()
             .with_enable_pruning(true)
             .with_skip_metadata(true);
+        self.listing_table_provider(Arc::new(file_format), ".parquet")
+            .await
+    }
+
+    async fn json_table_provider(&self) -> Result<ListingTable> {
+        debug!("get json table provider");
+        let compression = self.file_compression().await?;
+        let file_format = JsonFormat::default().with_file_compression_type(compression);
+        let file_extension = self.json_extension(compression).await?;
+        self.listing_table_provider(Arc::new(file_format), file_extension.as_str())
+            .await
+    }
 
+    /// Suffix of the listed json files: `jsonl` for .jsonl files, `json` otherwise (no
+    /// leading dot so that both .json and .ndjson files match)
+    async fn json_extension(&self, compression: FileCompressionType) -> Result<String> {
+        let json = format!("json{}", compression.get_ext());
+        let jsonl = format!(".jsonl{}", compression.get_ext());
+        if !self.path.path().ends_with('/') {
+            return Ok(if self.path.path().ends_with(&jsonl) {
+                jsonl
+            } else {
+                json
+            });
+        }
+        // directories of .jsonl files
+        self.register_object_store()?;
+        let url = ListingTableUrl::parse(self.path.as_str())?;
+        let store = self.ctx.runtime_env().object_store(&url)?;
+        let files: Vec<ObjectMeta> = url
+            .list_all_files(&self.ctx.state(), store.as_ref(), &jsonl)
+            .await?
+            .try_collect()
+            .await?;
+        Ok(if files.is_empty() { json } else { jsonl })
+    }
+
+    async fn csv_table_provider(&self) -> Result<ListingTable> {
+        debug!("get csv table provider");
+        let compression = self.file_compression().await?;
+        let file_format = CsvFormat::default()
+            .with_options(self.csv_options.clone())
+            .with_file_compression_type(compression);
+        let file_extension = format!(".csv{}", compression.get_ext());
+        self.listing_table_provider(Arc::new(file_format), file_extension.as_str())
+            .await
+    }
+
+    async fn avro_table_provider(&self) -> Result<ListingTable> {
+        debug!("get avro table provider");
+        self.listing_table_provider(Arc::new(AvroFormat), ".avro")
+            .await
+    }
+
+    /// Table format, inferred from the table path for `Format::Auto`
+    pub async fn format(&self) -> Result<Format> {
+        if self.fmt != Format::Auto {
+            return Ok(self.fmt);
+        }
+        if let Some(fmt) = self.resolved_fmt.get() {
+            return Ok(*fmt);
+        }
+        let fmt = match self.path.scheme() {
+            "file" => Format::infer_from_uri(self.path.path())?,
+            _ => self.probe_format().await?,
+        };
+        debug!("inferred {:?} format for {}", fmt, self.path);
+        Ok(*self.resolved_fmt.get_or_init(|| fmt))
+    }
+
+    /// Remote counterpart of `Format::infer_from_uri`, listing through the object store
+    async fn probe_format(&self) -> Result<Format> {
+        if let Some(fmt) = Format::from_extension(self.path.path()) {
+            return Ok(fmt);
+        }
+        self.register_object_store()?;
+        // probe the path as a directory
+        let dir = format!("{}/", self.path.as_str().trim_end_matches('/'));
+        let url = ListingTableUrl::parse(dir.as_str())?;
+        let store = self.ctx.runtime_env().object_store(&url)?;
+        let delta_log = url.prefix().child("_delta_log");
+        if store.list(Some(&delta_log)).try_next().await?.is_some() {
+            return Ok(Format::Delta);
+        }
+        let files: Vec<ObjectMeta> = url
+            .list_all_files(&self.ctx.state(), store.as_ref(), "")
+            .await?
+            .try_collect()
+            .await?;
+        files
+            .iter()
+            .filter_map(|meta| meta.location.filename())
+            .filter(|name| !name.starts_with('_') && !name.starts_with('.'))
+            .find_map(Format::from_extension)
+            .ok_or_else(|| {
+                anyhow!(
+                    "unable to infer the format of {}, expected a _delta_log folder or \
+                     .parquet, .csv, .json (.ndjson, .jsonl) or .avro files, use --format",
+                    self.path
+                )
+            })
+    }
+
+    /// Register the object store of remote tables in the session runtime
+    fn register_object_store(&self) -> Result<()> {
+        debug!("register store");
+        let url = &(self.path);
+        let store = match self.path.scheme() {
+            "s3" | "s3a" => Some(self.s3_object_store()?),
+            "gs" | "gcs" => Some(self.gcs_object_store()?),
+            "abfss" | "abfs" | "wasbs" | "wasb" => Some(self.azure_object_store()?),
+            "http" | "https" => Some(self.http_object_store()?),
+            _ => None,
+        };
+        if let Some(store) = store {
+            let store = self.cached(store, "")?;
+            let bucket_url =
+                Url::parse(&url[url::Position::BeforeScheme..url::Position::AfterHost])
+                    .context("Unable to get bucket based url")?;
+            let _ = self
+                .ctx
+                .runtime_env()
+                .object_store_registry
+                .register_store(&bucket_url, store);
+        }
+        Ok(())
+    }
+
+    /// Compression of csv/json files, from the override or the file extensions
+    ///
+    /// Directories are assumed to hold files compressed the same way, the first listed
+    /// file tells which, e.g. `events.csv.gz`.
+    async fn file_compression(&self) -> Result<FileCompressionType> {
+        if let Some(compression) = self.compression {
+            return Ok(compression);
+        }
+        if !self.path.path().ends_with('/') {
+            return Ok(compression_from_path(self.path.path()));
+        }
+        self.register_object_store()?;
+        let url = ListingTableUrl::parse(self.path.as_str())?;
+        let store = self.ctx.runtime_env().object_store(&url)?;
+        let state = self.ctx.state();
+        let files: Vec<ObjectMeta> = url
+            .list_all_files(&state, store.as_ref(), "")
+            .await?
+            .try_collect()
+            .await?;
+        // skip markers such as _SUCCESS or .crc files
+        let first = files.iter().find(|meta| {
+            meta.location
+                .filename()
+                .is_some_and(|name| !name.starts_with('_') && !name.starts_with('.'))
+        });
+        Ok(first
+            .map(|meta| compression_from_path(meta.location.as_ref()))
+            .unwrap_or(FileCompressionType::UNCOMPRESSED))
+    }
+
+    async fn listing_table_provider(
+        &self,
+        file_format: Arc<dyn FileFormat>,
+        file_extension: &str,
+    ) -> Result<ListingTable> {
+        self.register_object_store()?;
         let listing_common_options =
-            ListingOptions::new(Arc::new(file_format)).with_file_extension(".parquet");
+            ListingOptions::new(file_format).with_file_extension(file_extension);
 
         let listing_options = match self.partition_spec.clone() {
             Some(parts) => listing_common_options.with_table_partition_cols(parts),
@@ -113,10 +1141,33 @@ impl TableContext {
         };
 
         let path = ListingTableUrl::parse(self.path.as_str())?;
-        let table_config = ListingTableConfig::new(path)
-            .with_listing_options(listing_options)
-            .infer_schema(&self.ctx.state())
-            .await?;
+        let state = self.ctx.state();
+        let table_config = match self.sample_files {
+            Some(sample) => {
+                let store = self.ctx.runtime_env().object_store(&path)?;
+                let mut files: Vec<ObjectMeta> = path
+                    .list_all_files(&state, store.as_ref(), &listing_options.file_extension)
+                    .await?
+                    .try_collect()
+                    .await?;
+                files.sort_by(|a, b| a.location.cmp(&b.location));
+                files.truncate(sample.max(1));
+                debug!("infer schema from {} files", files.len());
+                let schema = listing_options
+                    .format
+                    .infer_schema(&state, &store, &files)
+                    .await?;
+                ListingTableConfig::new(path)
+                    .with_listing_options(listing_options)
+                    .with_schema(schema)
+            }
+            None => {
+                ListingTableConfig::new(path)
+                    .with_listing_options(listing_options)
+                    .infer_schema(&state)
+                    .await?
+            }
+        };
         let table = ListingTable::try_new(table_config)?;
         Ok(table)
     }
@@ -124,25 +1175,372 @@ impl TableContext {
     async fn delta_table_provider(&self) -> Result<DeltaTable> {
         debug!("get delta table provider");
         deltalake::aws::register_handlers(None);
-        Ok(DeltaTableBuilder::from_uri(self.path.as_str())
+        deltalake::gcp::register_handlers(None);
+        deltalake::azure::register_handlers(None);
+        let mut attempt = 0;
+        let table = loop {
+            // a log read racing with a commit/checkpoint rewrite can fail transiently,
+            // reloading the log from scratch usually recovers
+            match self.delta_table_builder()?.load().await {
+                Ok(table) => break table,
+                Err(err) if attempt < self.load_retries => {
+                    attempt += 1;
+                    warn!(
+                        "delta table load failed ({}), retry {}/{}",
+                        err, attempt, self.load_retries
+                    );
+                    tokio::time::sleep(Duration::from_millis(100 << attempt)).await;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        };
+        let _ = self.active_files.set(table.get_files_count());
+        let _ = self
+            .delta_partitions
+            .set(table.metadata()?.partition_columns.clone());
+        let _ = self.delta_table.set(table.clone());
+        Ok(table)
+    }
+
+    fn delta_table_builder(&self) -> Result<DeltaTableBuilder> {
+        let builder = DeltaTableBuilder::from_uri(self.path.as_str())
             .without_tombstones()
-            .load()
-            .await?)
+            .with_storage_options(self.storage_options.clone());
+        let builder = match (self.version, self.timestamp) {
+            (Some(version), _) => builder.with_version(version),
+            (None, Some(timestamp)) => builder.with_timestamp(timestamp),
+            (None, None) => builder,
+        };
+        let builder = match (self.path.scheme(), &self.cache_dir) {
+            ("s3" | "s3a", Some(_)) => {
+                // delta expects a store rooted at the table location
+                let table_root = Path::from_url_path(self.path.path())?;
+                let store = Arc::new(PrefixStore::new(self.s3_object_store()?, table_root));
+                let store = self.cached(store, self.path.path())?;
+                builder.with_storage_backend(store, self.path.clone())
+            }
+            _ => builder,
+        };
+        Ok(builder)
+    }
+
+    fn s3_object_store(&self) -> Result<Arc<dyn ObjectStore>> {
+        let s3 = s3_builder(&self.path, &self.storage_options)?
+            .build()
+            .context("Unable to create S3 object store")?;
+        Ok(Arc::new(s3))
+    }
+
+    fn gcs_object_store(&self) -> Result<Arc<dyn ObjectStore>> {
+        let gcs = gcs_builder(&self.path, &self.storage_options)?
+            .build()
+            .context("Unable to create GCS object store")?;
+        Ok(Arc::new(gcs))
+    }
+
+    fn azure_object_store(&self) -> Result<Arc<dyn ObjectStore>> {
+        // container and account are parsed from the url
+        let azure = MicrosoftAzureBuilder::from_env()
+            .with_url(self.path.as_str())
+            .build()
+            .context(AZURE_STORE_ERROR)?;
+        Ok(Arc::new(azure))
+    }
+
+    fn http_object_store(&self) -> Result<Arc<dyn ObjectStore>> {
+        // object paths are relative to the server origin
+        let http = HttpBuilder::new()
+            .with_url(&self.path[..url::Position::BeforePath])
+            .build()
+            .context("Unable to create HTTP object store")?;
+        Ok(Arc::new(http))
+    }
+
+    /// Wrap `store` into a local read-through cache when a cache dir is configured
+    fn cached(&self, store: Arc<dyn ObjectStore>, prefix: &str) -> Result<Arc<dyn ObjectStore>> {
+        match &self.cache_dir {
+            Some(dir) => {
+                let dir = dir
+                    .join(self.path.host_str().unwrap_or_default())
+                    .join(prefix.trim_matches('/'));
+                info!("cache remote parquet files in {}", dir.display());
+                Ok(Arc::new(CachedObjectStore::try_new(
+                    store,
+                    dir,
+                    self.cache_size,
+                )?))
+            }
+            None => Ok(store),
+        }
     }
 }
 
-fn get_partitions_spec(partitions: &Option<String>) -> Option<Vec<(String, DataType)>> {
-    if let Some(parts) = partitions.as_deref() {
-        let mut vec = Vec::new();
-        parts
-            .split(',')
-            .map(|s| s.trim())
-            .map(|s| s.split(':').collect())
-            .for_each(|t: Vec<&str>| {
-                vec.push((t[0].to_string(), crate::utils::type_from_str(t[1]).unwrap()))
-            });
-        Some(vec)
+#[derive(Default)]
+struct PruningSummary {
+    files_scanned: usize,
+    row_groups_matched: usize,
+    row_groups_pruned_statistics: usize,
+    row_groups_pruned_bloom_filter: usize,
+    pushdown_rows_filtered: usize,
+    page_index_rows_filtered: usize,
+}
+
+impl PruningSummary {
+    fn visit(&mut self, plan: &dyn ExecutionPlan) {
+        if let Some(parquet) = plan.as_any().downcast_ref::<ParquetExec>() {
+            self.files_scanned += parquet
+                .base_config()
+                .file_groups
+                .iter()
+                .map(|group| group.len())
+                .sum::<usize>();
+        }
+        if let Some(metrics) = plan.metrics() {
+            let sum = |name: &str| metrics.sum_by_name(name).map(|v| v.as_usize()).unwrap_or(0);
+            self.row_groups_matched += sum("row_groups_matched_statistics");
+            self.row_groups_pruned_statistics += sum("row_groups_pruned_statistics");
+            self.row_groups_pruned_bloom_filter += sum("row_groups_pruned_bloom_filter");
+            self.pushdown_rows_filtered += sum("pushdown_rows_filtered");
+            self.page_index_rows_filtered += sum("page_index_rows_filtered");
+        }
+        for child in plan.children() {
+            self.visit(child.as_ref());
+        }
+    }
+}
+
+fn get_partitions_spec(partitions: &Option<String>) -> Result<Option<Vec<(String, DataType)>>> {
+    partitions
+        .as_deref()
+        .map(|parts| {
+            crate::utils::parse_partition_spec(parts)
+                .map_err(|err| anyhow!("invalid partition spec: {}", err))
+        })
+        .transpose()
+}
+
+/// Parse a comma separated list of SQL expressions, e.g. `count(*),sum(amount)`
+///
+/// Commas nested in function calls are handled, the expressions are returned normalized.
+pub fn parse_expr_list(list: &str) -> Result<Vec<String>> {
+    let dialect = GenericDialect {};
+    let mut parser = Parser::new(&dialect).try_with_sql(list)?;
+    let exprs = parser.parse_comma_separated(Parser::parse_expr)?;
+    if parser.peek_token().token != Token::EOF {
+        bail!("unexpected {} in expression list", parser.peek_token());
+    }
+    Ok(exprs.iter().map(|e| e.to_string()).collect())
+}
+
+/// Translate the `col[:asc|desc]` keys of a comma separated sort spec into an ORDER BY
+/// clause, e.g. `amount:desc,id` becoming `"amount" DESC, "id"`
+///
+/// Keys name result columns: `renames` targets are sorted by their original column and
+/// `user.id` by its `user_id` projection. Other keys, e.g. `count(*) desc`, are kept as
+/// SQL. Also returns the result column names of the translated keys.
+pub fn order_by_clause(spec: &str, renames: &[(String, String)]) -> (String, Vec<String>) {
+    let is_column = |s: &str| {
+        !s.is_empty()
+            && s.chars()
+                .all(|c| c.is_alphanumeric() || c == '_' || c == '.')
+    };
+    // renames apply in order, undo them backwards
+    let unrenamed = |column: &str| {
+        renames
+            .iter()
+            .rev()
+            .fold(column.to_string(), |name, (old, new)| {
+                if &name == new {
+                    old.clone()
+                } else {
+                    name
+                }
+            })
+    };
+    let mut columns = vec![];
+    let keys: Vec<String> = spec
+        .split(',')
+        .map(|key| {
+            let key = key.trim();
+            let (column, direction) = match key.rsplit_once(':') {
+                Some((column, dir)) if dir.eq_ignore_ascii_case("asc") => (column, " ASC"),
+                Some((column, dir)) if dir.eq_ignore_ascii_case("desc") => (column, " DESC"),
+                _ => (key, ""),
+            };
+            if is_column(column) {
+                let column = column.replace('.', "_");
+                let key = format!("\"{}\"{}", unrenamed(&column), direction);
+                columns.push(column);
+                key
+            } else {
+                key.to_string()
+            }
+        })
+        .collect();
+    (keys.join(", "), columns)
+}
+
+/// Reverse each ordering of an ORDER BY clause, e.g. `ts desc, id` becoming `ts ASC, id DESC`
+///
+/// Null ordering defaults already follow the direction, explicit ones are flipped.
+pub fn reverse_order_by(order_by: &str) -> Result<String> {
+    let dialect = GenericDialect {};
+    let mut parser = Parser::new(&dialect).try_with_sql(order_by)?;
+    let mut exprs = parser.parse_comma_separated(Parser::parse_order_by_expr)?;
+    if parser.peek_token().token != Token::EOF {
+        bail!("unexpected {} in order by", parser.peek_token());
+    }
+    for expr in exprs.iter_mut() {
+        expr.asc = Some(!expr.asc.unwrap_or(true));
+        expr.nulls_first = expr.nulls_first.map(|first| !first);
+    }
+    Ok(exprs
+        .iter()
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>()
+        .join(", "))
+}
+
+/// Apply `(old, new)` column renames to a query result
+fn rename_columns(mut df: DataFrame, renames: &[(String, String)]) -> Result<DataFrame> {
+    for (old, new) in renames {
+        if !df.schema().has_column_with_unqualified_name(old) {
+            bail!("column {} to rename not found in query result", old);
+        }
+        df = df.with_column_renamed(old, new)?;
+    }
+    Ok(df)
+}
+
+/// Render a projected column, `user.id` becoming `"user"['id'] AS "user_id"`
+fn column_expr(column: &str) -> String {
+    let mut parts = column.split('.').map(|p| p.trim());
+    let root = parts.next().unwrap_or_default();
+    let fields: Vec<&str> = parts.collect();
+    if fields.is_empty() {
+        format!("\"{}\"", root)
     } else {
-        None
+        format!(
+            "\"{}\"{} AS \"{}_{}\"",
+            root,
+            fields
+                .iter()
+                .map(|f| format!("['{}']", f))
+                .collect::<String>(),
+            root,
+            fields.join("_")
+        )
+    }
+}
+
+fn flatten_projection(schema: &Schema) -> Vec<String> {
+    let mut projection = Vec::new();
+    for field in schema.fields() {
+        flatten_field(
+            field,
+            format!("\"{}\"", field.name()),
+            field.name().to_string(),
+            &mut projection,
+        );
+    }
+    projection
+}
+
+fn flatten_field(field: &Field, expr: String, alias: String, projection: &mut Vec<String>) {
+    match field.data_type() {
+        DataType::Struct(children) => children.iter().for_each(|child| {
+            flatten_field(
+                child,
+                format!("{}['{}']", expr, child.name()),
+                format!("{}_{}", alias, child.name()),
+                projection,
+            )
+        }),
+        _ => projection.push(format!("{} AS \"{}\"", expr, alias)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::aws::AmazonS3ConfigKey;
+    use object_store::ClientConfigKey;
+
+    #[test]
+    fn gs_store_is_registered() {
+        // credentials are only exchanged on the first request
+        let dir = tempfile::tempdir().unwrap();
+        let credentials = dir.path().join("credentials.json");
+        std::fs::write(
+            &credentials,
+            r#"{"type":"authorized_user","client_id":"id","client_secret":"secret","refresh_token":"token"}"#,
+        )
+        .unwrap();
+        let mut tblctx =
+            TableContext::new("gs://bucket/events/data.parquet", &None, Format::Parquet).unwrap();
+        tblctx.storage_options.insert(
+            "google_application_credentials".to_string(),
+            credentials.display().to_string(),
+        );
+        tblctx.register_object_store().unwrap();
+        let url = Url::parse("gs://bucket").unwrap();
+        assert!(tblctx.context().runtime_env().object_store(&url).is_ok());
+    }
+
+    #[test]
+    fn http_endpoint_allows_http() {
+        let tblctx = TableContext::new("s3://bucket/events/", &None, Format::Parquet)
+            .unwrap()
+            .with_s3_options(&Some("http://localhost:9000".to_string()), &None);
+        let builder = s3_builder(&tblctx.path, &tblctx.storage_options).unwrap();
+        let allow_http = AmazonS3ConfigKey::Client(ClientConfigKey::AllowHttp);
+        assert_eq!(
+            builder.get_config_value(&allow_http).as_deref(),
+            Some("true")
+        );
+        tblctx.register_object_store().unwrap();
+    }
+
+    #[tokio::test]
+    async fn struct_field_columns() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/nested.parquet");
+        let tblctx = TableContext::new(path, &None, Format::Parquet).unwrap();
+        tblctx.register_table().await.unwrap();
+        let options = QueryOptions {
+            columns: vec!["user.id".to_string(), "user.name".to_string()],
+            ..Default::default()
+        };
+        let query = tblctx.build_query("select * from tbl order by id".to_string(), 0, &options);
+        let batches = tblctx
+            .context()
+            .sql(&query)
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let batch = concat_batches(&batches[0].schema(), &batches).unwrap();
+        let names: Vec<&str> = batch
+            .schema_ref()
+            .fields()
+            .iter()
+            .map(|f| f.name().as_str())
+            .collect();
+        assert_eq!(names, vec!["user_id", "user_name"]);
+        let id = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+        assert_eq!(id.values().to_vec(), vec![10, 20]);
+        let name = batch
+            .column(1)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(
+            name.iter().collect::<Vec<_>>(),
+            vec![Some("alice"), Some("bob")]
+        );
     }
 }
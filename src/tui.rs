@@ -1,36 +1,776 @@
 use std::{
+    collections::HashMap,
     error::Error,
-    io,
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
     time::{Duration, Instant},
 };
 
+use anyhow::Context;
+use arboard::Clipboard;
+use clap::ValueEnum;
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyModifiers},
+    event::{
+        self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers,
+    },
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::arrow::util::display::{ArrayFormatter, FormatOptions};
+use datafusion::arrow::util::pretty::pretty_format_batches;
+use datafusion::physical_plan::SendableRecordBatchStream;
+use futures::StreamExt;
 use ratatui::{prelude::*, widgets::*};
+use serde::Deserialize;
+use tokio::runtime::Handle;
+
+use crate::cli::{BinaryDisplay, OutputFormat, Theme};
+use crate::utils::{display_binary, truncate_strings, Cancelled};
+
+/// Number of lines of the pretty formatted table header, kept visible while scrolling
+const HEADER_LINES: usize = 3;
+
+/// Lines fetched at once when a search runs past the fetched rows
+const FETCH_MORE_LINES: usize = 10_000;
 
 #[derive(Default)]
 struct Tui {
     pub vertical_scroll: u16,
+    /// Body line of the cursor, its cell being in the column at the left of the scrolled part
+    pub cursor_row: usize,
+    /// Number of body lines, updated on each draw
+    pub body_len: usize,
+    /// Offset of the scrolled part of lines, past the frozen columns
     pub horizontal_scroll: u16,
+    /// Width of the frozen leading columns, kept visible while scrolling horizontally
+    pub frozen_width: usize,
+    /// Start of each column of the table, from its top border
+    pub column_starts: Vec<usize>,
+    /// Height of the scrollable body, updated on each draw
+    pub page_height: u16,
+    /// Search pattern being typed after `/`
+    pub search_input: Option<String>,
+    /// Last confirmed search pattern, used by `n`/`N`
+    pub pattern: Option<String>,
+    /// Line the search being typed started from
+    pub search_origin: usize,
+    /// No match left in the fetched rows, `n` fetches more
+    pub fetch_prompt: bool,
+    /// Fetch more rows and search them on the next iteration
+    pub fetch_more: bool,
+    /// Export being typed after `e`
+    pub export_prompt: Option<ExportPrompt>,
+    /// Outcome of the last copy or export, shown in the status line
+    pub message: Option<String>,
 }
 
-pub fn show_in_tui(text: &str) -> Result<(), Box<dyn Error>> {
-    // setup terminal
-    enable_raw_mode()?;
-    let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
+/// Formats rows can be exported to from the TUI
+const EXPORT_FORMATS: [OutputFormat; 3] =
+    [OutputFormat::Csv, OutputFormat::Json, OutputFormat::Parquet];
+
+/// Writes the fetched rows to a path in the chosen format
+pub type Export<'a> = &'a dyn Fn(&str, OutputFormat, &[RecordBatch]) -> anyhow::Result<()>;
+
+/// TUI actions, bound to keys by `TuiConfig`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, ValueEnum)]
+pub enum Action {
+    Quit,
+    Search,
+    NextMatch,
+    PreviousMatch,
+    Top,
+    Bottom,
+    PageUp,
+    PageDown,
+    Up,
+    Down,
+    Left,
+    Right,
+    /// 20 lines up
+    FastUp,
+    /// 20 lines down
+    FastDown,
+    /// 20 chars left
+    FastLeft,
+    /// 20 chars right
+    FastRight,
+    Freeze,
+    Unfreeze,
+    Copy,
+    Export,
+}
+
+/// Key triggering an action, written `q`, `G`, `shift+down`, `ctrl+f` or `pagedown`
+///
+/// `shift+j` is the same key as `J`, shifted letters being matched as upper case chars.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct KeyBinding {
+    code: KeyCode,
+    /// `None` matches any modifiers
+    modifiers: Option<KeyModifiers>,
+}
+
+impl KeyBinding {
+    fn new(code: KeyCode) -> Self {
+        Self {
+            code,
+            modifiers: Some(KeyModifiers::NONE),
+        }
+    }
+
+    fn char(c: char) -> Self {
+        Self::new(KeyCode::Char(c))
+    }
+
+    fn shift(code: KeyCode) -> Self {
+        Self {
+            code,
+            modifiers: Some(KeyModifiers::SHIFT),
+        }
+    }
+
+    fn any_modifiers(code: KeyCode) -> Self {
+        Self {
+            code,
+            modifiers: None,
+        }
+    }
+
+    /// Whether a key event can trigger both bindings
+    fn overlaps(&self, other: &Self) -> bool {
+        self.code == other.code
+            && match (self.modifiers, other.modifiers) {
+                (Some(m), Some(n)) => m == n,
+                _ => true,
+            }
+    }
+
+    fn matches(&self, key: &KeyEvent) -> bool {
+        let modifiers = match key.code {
+            // terminals differ on reporting shift with upper case letters
+            KeyCode::Char(c) if c.is_uppercase() => key.modifiers.difference(KeyModifiers::SHIFT),
+            _ => key.modifiers,
+        };
+        self.code == key.code && self.modifiers.map_or(true, |m| m == modifiers)
+    }
+}
+
+impl FromStr for KeyBinding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (prefix, key) = s.rsplit_once('+').unwrap_or(("", s));
+        let mut modifiers = KeyModifiers::NONE;
+        for modifier in prefix.split('+').filter(|m| !m.is_empty()) {
+            modifiers |= match modifier.to_lowercase().as_str() {
+                "shift" => KeyModifiers::SHIFT,
+                "ctrl" => KeyModifiers::CONTROL,
+                "alt" => KeyModifiers::ALT,
+                _ => return Err(format!("unknown modifier {} in key {}", modifier, s)),
+            };
+        }
+        let code = match key.to_lowercase().as_str() {
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "esc" => KeyCode::Esc,
+            "enter" => KeyCode::Enter,
+            "tab" => KeyCode::Tab,
+            "space" => KeyCode::Char(' '),
+            _ => {
+                let mut chars = key.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => KeyCode::Char(c),
+                    _ => return Err(format!("unknown key {}", s)),
+                }
+            }
+        };
+        let (code, modifiers) = match code {
+            // shift is not reported consistently with letters, match the upper case char
+            KeyCode::Char(c) if c.is_alphabetic() && modifiers.contains(KeyModifiers::SHIFT) => (
+                KeyCode::Char(c.to_uppercase().next().unwrap_or(c)),
+                modifiers.difference(KeyModifiers::SHIFT),
+            ),
+            _ => (code, modifiers),
+        };
+        Ok(Self {
+            code,
+            modifiers: Some(modifiers),
+        })
+    }
+}
+
+impl TryFrom<String> for KeyBinding {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Theme and key bindings of the TUI
+///
+/// Defaults can be overridden in `$XDG_CONFIG_HOME/adt/tui.toml`, each listed action
+/// replacing all its default keys, e.g.
+///
+/// ```toml
+/// theme = "light"
+///
+/// [keys]
+/// quit = ["q", "esc"]
+/// fast-down = ["shift+j", "shift+down", "ctrl+d"]
+/// ```
+#[derive(Clone, Debug)]
+pub struct TuiConfig {
+    pub theme: Theme,
+    pub binary_display: BinaryDisplay,
+    pub max_column_width: Option<usize>,
+    /// abort fetching rows after this many seconds, as `--timeout-secs` does for queries
+    pub timeout_secs: Option<u64>,
+    keys: HashMap<Action, Vec<KeyBinding>>,
+}
+
+impl Default for TuiConfig {
+    fn default() -> Self {
+        use KeyCode::*;
+        let keys = HashMap::from([
+            (Action::Quit, vec![KeyBinding::char('q')]),
+            (Action::Search, vec![KeyBinding::char('/')]),
+            (Action::NextMatch, vec![KeyBinding::char('n')]),
+            (Action::PreviousMatch, vec![KeyBinding::char('N')]),
+            (
+                Action::Top,
+                vec![KeyBinding::char('g'), KeyBinding::any_modifiers(Home)],
+            ),
+            (
+                Action::Bottom,
+                vec![KeyBinding::char('G'), KeyBinding::any_modifiers(End)],
+            ),
+            (Action::PageUp, vec![KeyBinding::any_modifiers(PageUp)]),
+            (Action::PageDown, vec![KeyBinding::any_modifiers(PageDown)]),
+            (Action::Up, vec![KeyBinding::char('k'), KeyBinding::new(Up)]),
+            (
+                Action::Down,
+                vec![KeyBinding::char('j'), KeyBinding::new(Down)],
+            ),
+            (
+                Action::Left,
+                vec![KeyBinding::char('h'), KeyBinding::new(Left)],
+            ),
+            (
+                Action::Right,
+                vec![KeyBinding::char('l'), KeyBinding::new(Right)],
+            ),
+            (
+                Action::FastUp,
+                vec![KeyBinding::char('K'), KeyBinding::shift(Up)],
+            ),
+            (
+                Action::FastDown,
+                vec![KeyBinding::char('J'), KeyBinding::shift(Down)],
+            ),
+            (
+                Action::FastLeft,
+                vec![KeyBinding::char('H'), KeyBinding::shift(Left)],
+            ),
+            (
+                Action::FastRight,
+                vec![KeyBinding::char('L'), KeyBinding::shift(Right)],
+            ),
+            (Action::Freeze, vec![KeyBinding::char('f')]),
+            (Action::Unfreeze, vec![KeyBinding::char('F')]),
+            (Action::Copy, vec![KeyBinding::char('y')]),
+            (Action::Export, vec![KeyBinding::char('e')]),
+        ]);
+        Self {
+            theme: Theme::default(),
+            binary_display: BinaryDisplay::default(),
+            max_column_width: None,
+            timeout_secs: None,
+            keys,
+        }
+    }
+}
+
+/// Content of the TUI config file
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct TuiConfigFile {
+    theme: Option<Theme>,
+    keys: HashMap<String, Vec<KeyBinding>>,
+}
+
+impl TuiConfig {
+    /// Defaults overridden by the config file, if any, then by `theme`
+    pub fn load(theme: Option<Theme>) -> anyhow::Result<Self> {
+        let mut config = Self::default();
+        let path = std::env::var("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|_| std::env::var("HOME").map(|home| Path::new(&home).join(".config")))
+            .map(|dir| dir.join("adt").join("tui.toml"))
+            .ok()
+            .filter(|path| path.exists());
+        if let Some(path) = path {
+            let text = fs::read_to_string(path)
+                .with_context(|| format!("Unable to read {}", path.display()))?;
+            let file: TuiConfigFile = toml::from_str(&text)
+                .with_context(|| format!("Invalid TUI config {}", path.display()))?;
+            for (name, keys) in file.keys {
+                let action = Action::from_str(&name, true).map_err(|_| {
+                    anyhow::anyhow!("Unknown TUI action {} in {}", name, path.display())
+                })?;
+                config.keys.insert(action, keys);
+            }
+            config
+                .check_keys()
+                .with_context(|| format!("Invalid TUI config {}", path.display()))?;
+            if let Some(theme) = file.theme {
+                config = config.with_theme(theme);
+            }
+        }
+        if let Some(theme) = theme {
+            config = config.with_theme(theme);
+        }
+        Ok(config)
+    }
+
+    pub fn with_theme(mut self, theme: Theme) -> Self {
+        self.theme = theme;
+        self
+    }
+
+    pub fn with_binary_display(mut self, binary_display: BinaryDisplay) -> Self {
+        self.binary_display = binary_display;
+        self
+    }
+
+    pub fn with_max_column_width(mut self, max_column_width: Option<usize>) -> Self {
+        self.max_column_width = max_column_width;
+        self
+    }
+
+    pub fn with_timeout_secs(mut self, timeout_secs: Option<u64>) -> Self {
+        self.timeout_secs = timeout_secs;
+        self
+    }
+
+    /// Reject keys bound to several actions, which would trigger any of them
+    fn check_keys(&self) -> anyhow::Result<()> {
+        let mut bound: Vec<(Action, &KeyBinding)> = vec![];
+        for (action, keys) in &self.keys {
+            for key in keys {
+                if let Some((other, _)) = bound.iter().find(|(_, k)| k.overlaps(key)) {
+                    anyhow::bail!(
+                        "key {:?} bound to both {:?} and {:?}",
+                        key.code,
+                        other,
+                        action
+                    );
+                }
+                bound.push((*action, key));
+            }
+        }
+        Ok(())
+    }
+
+    fn action(&self, key: &KeyEvent) -> Option<Action> {
+        self.keys
+            .iter()
+            .find(|(_, keys)| keys.iter().any(|k| k.matches(key)))
+            .map(|(action, _)| *action)
+    }
+
+    /// Style of the table
+    fn text_style(&self) -> Style {
+        match self.theme {
+            Theme::Dark => Style::new().gray(),
+            Theme::Light => Style::new().black(),
+        }
+    }
+
+    /// Style of the status line
+    fn status_style(&self) -> Style {
+        match self.theme {
+            Theme::Dark => Style::new().dark_gray(),
+            Theme::Light => Style::new().gray(),
+        }
+    }
+}
+
+/// Export prompt, asking for the path then for the format
+enum ExportPrompt {
+    Path(String),
+    Format { path: String, input: String },
+}
+
+impl ExportPrompt {
+    fn input(&mut self) -> &mut String {
+        match self {
+            ExportPrompt::Path(path) => path,
+            ExportPrompt::Format { input, .. } => input,
+        }
+    }
+}
+
+/// Lines shown by the TUI, a pretty formatted table whose rows can be fetched lazily
+struct Rows {
+    /// pending result batches, `None` once exhausted or for pre-rendered text
+    stream: Option<SendableRecordBatchStream>,
+    schema: Option<SchemaRef>,
+    batches: Vec<RecordBatch>,
+    lines: Vec<String>,
+    binary_display: BinaryDisplay,
+    max_column_width: Option<usize>,
+    timeout_secs: Option<u64>,
+}
+
+impl Rows {
+    fn from_text(text: &str) -> Self {
+        Self {
+            stream: None,
+            schema: None,
+            batches: vec![],
+            lines: text.lines().map(String::from).collect(),
+            binary_display: BinaryDisplay::default(),
+            max_column_width: None,
+            timeout_secs: None,
+        }
+    }
+
+    fn from_stream(
+        stream: SendableRecordBatchStream,
+        config: &TuiConfig,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut rows = Self {
+            schema: Some(stream.schema()),
+            stream: Some(stream),
+            batches: vec![],
+            lines: vec![],
+            binary_display: config.binary_display,
+            max_column_width: config.max_column_width,
+            timeout_secs: config.timeout_secs,
+        };
+        rows.render()?;
+        Ok(rows)
+    }
+
+    /// Fetch batches until there are at least `lines` lines or the stream is exhausted
+    ///
+    /// Fetching blocks the current tokio worker, which requires a multi-threaded runtime.
+    /// Each fetch fails with `Cancelled` after `timeout_secs` or on Ctrl-C.
+    fn fetch(&mut self, lines: usize) -> Result<(), Box<dyn Error>> {
+        // each row being one line, estimate the lines before rendering
+        let mut estimate = self.lines.len();
+        let mut fetched = false;
+        while estimate < lines {
+            let Some(stream) = self.stream.as_mut() else {
+                break;
+            };
+            let timeout_secs = self.timeout_secs;
+            let next = tokio::task::block_in_place(|| {
+                Handle::current().block_on(async {
+                    let deadline = async {
+                        match timeout_secs {
+                            Some(secs) => tokio::time::sleep(Duration::from_secs(secs)).await,
+                            None => std::future::pending().await,
+                        }
+                    };
+                    tokio::select! {
+                        next = stream.next() => Ok(next),
+                        _ = deadline => Err(Cancelled::TimedOut(timeout_secs.unwrap_or_default())),
+                        _ = ctrl_c_pressed() => Err(Cancelled::Interrupted),
+                    }
+                })
+            })?;
+            match next {
+                Some(batch) => {
+                    let batch = batch?;
+                    estimate += batch.num_rows();
+                    self.batches.push(batch);
+                    fetched = true;
+                }
+                None => self.stream = None,
+            }
+        }
+        if fetched {
+            self.render()?;
+        }
+        Ok(())
+    }
+
+    /// Pretty format the fetched batches, the column widths fitting the fetched rows
+    fn render(&mut self) -> Result<(), Box<dyn Error>> {
+        let table = match (&self.schema, self.batches.is_empty()) {
+            (Some(schema), true) => {
+                pretty_format_batches(&[RecordBatch::new_empty(schema.clone())])?
+            }
+            // fetched batches keep their values for copy and export
+            _ => {
+                let batches = display_binary(&self.batches, self.binary_display)?;
+                match self.max_column_width {
+                    Some(width) => pretty_format_batches(&truncate_strings(&batches, width)?)?,
+                    None => pretty_format_batches(&batches)?,
+                }
+            }
+        };
+        self.lines = table.to_string().lines().map(String::from).collect();
+        Ok(())
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.stream.is_none()
+    }
+
+    /// Value of the cell at body line `row` and column `column`
+    ///
+    /// Taken from the fetched batches when there are some, each row being a line, else
+    /// from the pre-rendered text.
+    fn cell(&self, row: usize, column: usize) -> Option<String> {
+        let mut row = row;
+        for batch in &self.batches {
+            if row < batch.num_rows() {
+                let array = batch.columns().get(column)?;
+                let formatter = ArrayFormatter::try_new(array.as_ref(), &FormatOptions::default());
+                return formatter.ok().map(|f| f.value(row).to_string());
+            }
+            row -= batch.num_rows();
+        }
+        if !self.batches.is_empty() {
+            return None;
+        }
+        let line = self.lines.get(HEADER_LINES + row)?;
+        if !line.starts_with('|') {
+            return None;
+        }
+        line.split('|')
+            .nth(column + 1)
+            .map(|cell| cell.trim().to_string())
+    }
+}
+
+/// Resolve once Ctrl-C is pressed, which comes as a key event in raw mode rather than SIGINT
+///
+/// Other keys pressed meanwhile are dropped.
+async fn ctrl_c_pressed() {
+    loop {
+        while event::poll(Duration::ZERO).unwrap_or(false) {
+            if let Ok(Event::Key(key)) = event::read() {
+                if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+                    return;
+                }
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+/// Start offsets (in chars) of the columns of a pretty formatted table, from its top
+/// border `+----+----+`, a column starting right after the separator of the previous one
+fn column_starts(border: &str) -> Vec<usize> {
+    let separators: Vec<usize> = border
+        .chars()
+        .enumerate()
+        .filter(|(_, c)| *c == '+')
+        .map(|(i, _)| i)
+        .collect();
+    if separators.len() < 2 {
+        return vec![0];
+    }
+    std::iter::once(0)
+        .chain(separators[1..separators.len() - 1].iter().map(|s| s + 1))
+        .collect()
+}
+
+/// Visible part of `line`: its first `frozen` chars followed by the ones from `frozen + scroll`
+fn viewport(line: &str, frozen: usize, scroll: usize) -> String {
+    line.chars()
+        .take(frozen)
+        .chain(line.chars().skip(frozen + scroll))
+        .collect()
+}
+
+impl Tui {
+    /// Move the cursor to body line `line`, scrolling to keep it visible
+    fn scroll_to(&mut self, line: usize) {
+        self.cursor_row = line.min(self.body_len.saturating_sub(1));
+        let page = self.page_height.max(1) as usize;
+        let top = self.vertical_scroll as usize;
+        if self.cursor_row < top {
+            self.vertical_scroll = self.cursor_row as u16;
+        } else if self.cursor_row >= top + page {
+            self.vertical_scroll = (self.cursor_row + 1 - page) as u16;
+        }
+    }
+
+    /// Index of the cursor column, the one at the left edge of the scrolled part
+    fn cursor_column(&self) -> usize {
+        let offset = self.scroll_offset();
+        self.column_starts
+            .iter()
+            .filter(|&&s| s <= offset)
+            .count()
+            .saturating_sub(1)
+    }
+
+    /// Offset of the first scrolled char in the full line
+    fn scroll_offset(&self) -> usize {
+        self.frozen_width + self.horizontal_scroll as usize
+    }
+
+    /// Scroll to the start of the next (or previous) column
+    fn scroll_column(&mut self, forward: bool) {
+        let offset = self.scroll_offset();
+        let target = if forward {
+            self.column_starts.iter().find(|&&s| s > offset).copied()
+        } else {
+            let previous = self.column_starts.iter().rev().find(|&&s| s < offset);
+            Some(previous.copied().unwrap_or(0).max(self.frozen_width))
+        };
+        if let Some(target) = target {
+            self.horizontal_scroll = (target - self.frozen_width) as u16;
+        }
+    }
+
+    /// Freeze the columns up to the one at the left edge of the scrolled part
+    fn freeze_column(&mut self) {
+        let offset = self.scroll_offset();
+        if let Some(end) = self.column_starts.iter().find(|&&s| s > offset) {
+            self.frozen_width = *end;
+            self.horizontal_scroll = 0;
+        }
+    }
+
+    /// Unfreeze all columns, keeping the scrolled part in place
+    fn unfreeze_columns(&mut self) {
+        self.horizontal_scroll = self.scroll_offset() as u16;
+        self.frozen_width = 0;
+    }
+
+    /// Scroll to the first body line with a cell containing the search pattern, looking
+    /// forward from line `from` included, or backward from the line before it
+    ///
+    /// The search wraps around the fetched lines when `wrap` is set, i.e. once every row
+    /// is fetched. Returns whether a match was found.
+    fn jump_to_match(&mut self, lines: &[String], from: usize, forward: bool, wrap: bool) -> bool {
+        let Some(pattern) = &self.pattern else {
+            return false;
+        };
+        let n = lines.len();
+        let from = from.min(n);
+        // lines searched after wrapping around
+        let (before, after) = if wrap {
+            (0..from, from..n)
+        } else {
+            (0..0, n..n)
+        };
+        let found = if forward {
+            (from..n)
+                .chain(before)
+                .find(|&i| cell_match(&lines[i], pattern))
+        } else {
+            (0..from)
+                .rev()
+                .chain(after.rev())
+                .find(|&i| cell_match(&lines[i], pattern))
+        };
+        if let Some(i) = found {
+            self.scroll_to(i);
+        }
+        found.is_some()
+    }
+}
+
+/// Whether a cell of a pretty formatted table line contains `pattern`, borders excluded
+fn cell_match(line: &str, pattern: &str) -> bool {
+    !pattern.is_empty()
+        && !line.starts_with('+')
+        && line.split('|').any(|cell| cell.contains(pattern))
+}
+
+/// Line with the cursor cell, `width` chars after the `frozen` ones, highlighted
+fn cursor_line(line: String, frozen: usize, width: usize) -> Line<'static> {
+    let chars: Vec<char> = line.chars().collect();
+    let start = frozen.min(chars.len());
+    let end = start.saturating_add(width).min(chars.len());
+    Line::from(vec![
+        Span::raw(chars[..start].iter().collect::<String>()),
+        Span::raw(chars[start..end].iter().collect::<String>()).reversed(),
+        Span::raw(chars[end..].iter().collect::<String>()),
+    ])
+}
+
+/// Line with the occurrences of `pattern` highlighted
+fn highlight(line: String, pattern: &str) -> Line<'static> {
+    let mut spans = vec![];
+    let mut rest = line.as_str();
+    while let Some(i) = rest.find(pattern) {
+        spans.push(Span::raw(rest[..i].to_string()));
+        spans.push(Span::raw(pattern.to_string()).black().on_yellow());
+        rest = &rest[i + pattern.len()..];
+    }
+    spans.push(Span::raw(rest.to_string()));
+    Line::from(spans)
+}
+
+/// Show a query result in the TUI, fetching batches from `stream` as the user scrolls
+///
+/// `e` exports the fetched rows through `export`.
+pub fn show_in_tui(
+    stream: SendableRecordBatchStream,
+    export: Export,
+    config: &TuiConfig,
+) -> Result<(), Box<dyn Error>> {
+    run(Rows::from_stream(stream, config)?, Some(export), config)
+}
+
+/// Show pre-rendered text, e.g. a small pretty formatted table, in the TUI
+pub fn show_text_in_tui(text: &str, config: &TuiConfig) -> Result<(), Box<dyn Error>> {
+    run(Rows::from_text(text), None, config)
+}
+
+fn run(rows: Rows, export: Option<Export>, config: &TuiConfig) -> Result<(), Box<dyn Error>> {
+    let mut terminal = setup_terminal()?;
 
     // create tui and run it
     let tick_rate = Duration::from_millis(250);
     let tui = Tui::default();
-    let res = run_tui(&mut terminal, tui, tick_rate, text);
+    let res = run_tui(&mut terminal, tui, tick_rate, rows, export, config);
 
-    // restore terminal
+    restore_terminal(&mut terminal)?;
+
+    // values copied without clipboard, e.g. on a headless host
+    res?.iter().for_each(|value| eprintln!("{}", value));
+    Ok(())
+}
+
+/// Let the user toggle columns in a list, returns the selected ones or `None` if cancelled
+pub fn pick_columns(columns: &[String]) -> Result<Option<Vec<String>>, Box<dyn Error>> {
+    let mut terminal = setup_terminal()?;
+    let res = run_picker(&mut terminal, columns);
+    restore_terminal(&mut terminal)?;
+    Ok(res?)
+}
+
+fn setup_terminal() -> Result<Terminal<CrosstermBackend<io::Stdout>>, Box<dyn Error>> {
+    enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
+    let backend = CrosstermBackend::new(stdout);
+    Ok(Terminal::new(backend)?)
+}
+
+fn restore_terminal(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+) -> Result<(), Box<dyn Error>> {
     disable_raw_mode()?;
     execute!(
         terminal.backend_mut(),
@@ -38,54 +778,261 @@ pub fn show_in_tui(text: &str) -> Result<(), Box<dyn Error>> {
         DisableMouseCapture
     )?;
     terminal.show_cursor()?;
+    Ok(())
+}
 
-    if let Err(err) = res {
-        println!("{err:?}");
-    }
+fn run_picker<B: Backend>(
+    terminal: &mut Terminal<B>,
+    columns: &[String],
+) -> io::Result<Option<Vec<String>>> {
+    let mut selected = vec![false; columns.len()];
+    let mut state = ListState::default().with_selected(Some(0));
+    loop {
+        terminal.draw(|f| {
+            let [list_area, status_area] =
+                Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(f.size());
+            let items: Vec<ListItem> = columns
+                .iter()
+                .zip(selected.iter())
+                .map(|(col, on)| {
+                    ListItem::new(format!("[{}] {}", if *on { "x" } else { " " }, col))
+                })
+                .collect();
+            let list = List::new(items)
+                .block(Block::bordered().title("columns"))
+                .highlight_style(Style::new().reversed());
+            f.render_stateful_widget(list, list_area, &mut state);
+            f.render_widget(
+                Paragraph::new("space toggle, a all/none, enter run query, q cancel").dark_gray(),
+                status_area,
+            );
+        })?;
 
-    Ok(())
+        if let Event::Key(key) = event::read()? {
+            let current = state.selected().unwrap_or(0);
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(None),
+                KeyCode::Char('j') | KeyCode::Down => {
+                    state.select(Some((current + 1).min(columns.len().saturating_sub(1))));
+                }
+                KeyCode::Char('k') | KeyCode::Up => state.select(Some(current.saturating_sub(1))),
+                KeyCode::Char(' ') => {
+                    if let Some(on) = selected.get_mut(current) {
+                        *on = !*on;
+                    }
+                }
+                KeyCode::Char('a') => {
+                    let all = selected.iter().all(|on| *on);
+                    selected.iter_mut().for_each(|on| *on = !all);
+                }
+                KeyCode::Enter => {
+                    let chosen: Vec<String> = columns
+                        .iter()
+                        .zip(selected.iter())
+                        .filter(|(_, on)| **on)
+                        .map(|(col, _)| col.clone())
+                        .collect();
+                    if !chosen.is_empty() {
+                        return Ok(Some(chosen));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
 }
 
 fn run_tui<B: Backend>(
     terminal: &mut Terminal<B>,
     mut tui: Tui,
     tick_rate: Duration,
-    text: &str,
-) -> io::Result<()> {
+    mut rows: Rows,
+    export: Option<Export>,
+    config: &TuiConfig,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut clipboard = Clipboard::new().ok();
+    let mut uncopied = vec![];
     let mut last_tick = Instant::now();
     loop {
-        terminal.draw(|f| ui(f, &mut tui, text))?;
+        // keep a page ahead of the viewport fetched
+        let wanted = HEADER_LINES + tui.vertical_scroll as usize + 2 * tui.page_height as usize;
+        rows.fetch(wanted)?;
+        let searched = rows.lines.len();
+        if tui.fetch_more {
+            rows.fetch(searched + FETCH_MORE_LINES)?;
+        }
+        let (header, body) = rows.lines.split_at(HEADER_LINES.min(rows.lines.len()));
+        if tui.fetch_more {
+            // resume the search with the newly fetched rows
+            tui.fetch_more = false;
+            let from = searched.saturating_sub(HEADER_LINES);
+            tui.fetch_prompt =
+                !tui.jump_to_match(body, from, true, rows.is_exhausted()) && !rows.is_exhausted();
+        }
+        tui.column_starts = column_starts(header.first().map_or("", |l| l.as_str()));
+        tui.body_len = body.len();
+        terminal.draw(|f| ui(f, &mut tui, config, header, body, rows.is_exhausted()))?;
 
         let timeout = tick_rate.saturating_sub(last_tick.elapsed());
         if crossterm::event::poll(timeout)? {
             if let Event::Key(key) = event::read()? {
-                match (key.code, key.modifiers) {
-                    (KeyCode::Char('q'), KeyModifiers::NONE) => return Ok(()),
-                    (KeyCode::Char('j') | KeyCode::Down, KeyModifiers::NONE) => {
-                        tui.vertical_scroll = tui.vertical_scroll.saturating_add(1);
+                let exhausted = rows.is_exhausted();
+                if let Some(input) = tui.search_input.as_mut() {
+                    match key.code {
+                        KeyCode::Enter => {
+                            tui.search_input = None;
+                            let found = tui.jump_to_match(body, tui.search_origin, true, exhausted);
+                            tui.fetch_prompt = !found && !exhausted;
+                        }
+                        KeyCode::Esc => {
+                            tui.search_input = None;
+                            tui.pattern = None;
+                            tui.scroll_to(tui.search_origin);
+                        }
+                        KeyCode::Backspace => {
+                            input.pop();
+                        }
+                        KeyCode::Char(c) => input.push(c),
+                        _ => {}
+                    }
+                    // incremental search, from where it started
+                    if let Some(input) = &tui.search_input {
+                        tui.pattern = Some(input.clone()).filter(|p| !p.is_empty());
+                        if !tui.jump_to_match(body, tui.search_origin, true, exhausted) {
+                            tui.scroll_to(tui.search_origin);
+                        }
+                    }
+                    continue;
+                }
+                if let Some(prompt) = tui.export_prompt.as_mut() {
+                    match key.code {
+                        KeyCode::Esc => tui.export_prompt = None,
+                        KeyCode::Backspace => {
+                            prompt.input().pop();
+                        }
+                        KeyCode::Char(c) => prompt.input().push(c),
+                        KeyCode::Enter => match tui.export_prompt.take() {
+                            Some(ExportPrompt::Path(path)) if !path.is_empty() => {
+                                // a supported path extension is the default format
+                                let input = Path::new(&path)
+                                    .extension()
+                                    .and_then(|e| e.to_str())
+                                    .and_then(|e| OutputFormat::from_str(e, true).ok())
+                                    .filter(|fmt| EXPORT_FORMATS.contains(fmt))
+                                    .and_then(|fmt| fmt.to_possible_value())
+                                    .map(|v| v.get_name().to_string())
+                                    .unwrap_or_default();
+                                tui.export_prompt = Some(ExportPrompt::Format { path, input });
+                            }
+                            Some(ExportPrompt::Format { path, input }) => {
+                                match OutputFormat::from_str(&input, true)
+                                    .ok()
+                                    .filter(|fmt| EXPORT_FORMATS.contains(fmt))
+                                    .zip(export)
+                                {
+                                    Some((fmt, export)) => {
+                                        let count: usize =
+                                            rows.batches.iter().map(|b| b.num_rows()).sum();
+                                        tui.message =
+                                            Some(match export(&path, fmt, &rows.batches) {
+                                                Ok(()) => {
+                                                    format!("{} rows exported to {}", count, path)
+                                                }
+                                                Err(err) => format!("export fails: {:#}", err),
+                                            });
+                                    }
+                                    None => {
+                                        tui.export_prompt =
+                                            Some(ExportPrompt::Format { path, input })
+                                    }
+                                }
+                            }
+                            prompt => tui.export_prompt = prompt,
+                        },
+                        _ => {}
+                    }
+                    continue;
+                }
+                let prompted = std::mem::take(&mut tui.fetch_prompt);
+                tui.message = None;
+                match config.action(&key) {
+                    Some(Action::Quit) => return Ok(uncopied),
+                    Some(Action::Search) => {
+                        tui.search_input = Some(String::new());
+                        tui.search_origin = tui.cursor_row;
+                    }
+                    Some(Action::Export) if export.is_some() => {
+                        tui.export_prompt = Some(ExportPrompt::Path(String::new()));
+                    }
+                    Some(Action::Export) => {
+                        tui.message = Some(String::from("only query results can be exported"));
                     }
-                    (KeyCode::Char('k') | KeyCode::Up, KeyModifiers::NONE) => {
-                        tui.vertical_scroll = tui.vertical_scroll.saturating_sub(1);
+                    Some(Action::Copy) => {
+                        let Some(value) = rows.cell(tui.cursor_row, tui.cursor_column()) else {
+                            continue;
+                        };
+                        let copied = clipboard
+                            .as_mut()
+                            .is_some_and(|c| c.set_text(value.clone()).is_ok());
+                        tui.message = Some(if copied {
+                            String::from("cell copied to the clipboard")
+                        } else {
+                            uncopied.push(value);
+                            String::from("no clipboard, the cell is printed on exit")
+                        });
                     }
-                    (KeyCode::Char('h') | KeyCode::Left, KeyModifiers::NONE) => {
-                        tui.horizontal_scroll = tui.horizontal_scroll.saturating_sub(1);
+                    Some(Action::NextMatch) if prompted => tui.fetch_more = true,
+                    Some(Action::NextMatch) => {
+                        let from = tui.cursor_row + 1;
+                        tui.fetch_prompt =
+                            !tui.jump_to_match(body, from, true, exhausted) && !exhausted;
                     }
-                    (KeyCode::Char('l') | KeyCode::Right, KeyModifiers::NONE) => {
-                        tui.horizontal_scroll = tui.horizontal_scroll.saturating_add(1);
+                    Some(Action::PreviousMatch) => {
+                        let from = tui.cursor_row;
+                        tui.jump_to_match(body, from, false, exhausted);
                     }
-                    (KeyCode::Char('j') | KeyCode::Down, KeyModifiers::SHIFT) => {
-                        tui.vertical_scroll = tui.vertical_scroll.saturating_add(20);
+                    Some(Action::Top) => {
+                        tui.scroll_to(0);
                     }
-                    (KeyCode::Char('k') | KeyCode::Up, KeyModifiers::SHIFT) => {
-                        tui.vertical_scroll = tui.vertical_scroll.saturating_sub(20);
+                    Some(Action::Bottom) => {
+                        // the bottom is only known once every batch is fetched
+                        rows.fetch(usize::MAX)?;
+                        tui.body_len = rows.lines.len().saturating_sub(HEADER_LINES);
+                        tui.scroll_to(tui.body_len);
                     }
-                    (KeyCode::Char('h') | KeyCode::Left, KeyModifiers::SHIFT) => {
+                    Some(Action::PageDown) => {
+                        tui.scroll_to(tui.cursor_row + tui.page_height as usize);
+                    }
+                    Some(Action::PageUp) => {
+                        tui.scroll_to(tui.cursor_row.saturating_sub(tui.page_height as usize));
+                    }
+                    Some(Action::Down) => {
+                        tui.scroll_to(tui.cursor_row + 1);
+                    }
+                    Some(Action::Up) => {
+                        tui.scroll_to(tui.cursor_row.saturating_sub(1));
+                    }
+                    Some(Action::Left) => {
+                        tui.scroll_column(false);
+                    }
+                    Some(Action::Right) => {
+                        tui.scroll_column(true);
+                    }
+                    Some(Action::Freeze) => tui.freeze_column(),
+                    Some(Action::Unfreeze) => tui.unfreeze_columns(),
+                    Some(Action::FastDown) => {
+                        tui.scroll_to(tui.cursor_row + 20);
+                    }
+                    Some(Action::FastUp) => {
+                        tui.scroll_to(tui.cursor_row.saturating_sub(20));
+                    }
+                    Some(Action::FastLeft) => {
                         tui.horizontal_scroll = tui.horizontal_scroll.saturating_sub(20);
                     }
-                    (KeyCode::Char('l') | KeyCode::Right, KeyModifiers::SHIFT) => {
+                    Some(Action::FastRight) => {
                         tui.horizontal_scroll = tui.horizontal_scroll.saturating_add(20);
                     }
-                    _ => {}
+                    None => {}
                 }
             }
         }
@@ -95,11 +1042,134 @@ fn run_tui<B: Backend>(
     }
 }
 
-fn ui(f: &mut Frame, tui: &Tui, text: &str) {
-    let area = f.size();
+fn ui(
+    f: &mut Frame,
+    tui: &mut Tui,
+    config: &TuiConfig,
+    header: &[String],
+    body: &[String],
+    exhausted: bool,
+) {
+    let [header_area, body_area, status_area] = Layout::vertical([
+        Constraint::Length(HEADER_LINES as u16),
+        Constraint::Min(0),
+        Constraint::Length(1),
+    ])
+    .areas(f.size());
+    tui.page_height = body_area.height;
+
+    let (frozen, scroll) = (tui.frozen_width, tui.horizontal_scroll as usize);
+    let header: Vec<Line> = header
+        .iter()
+        .map(|line| Line::from(viewport(line, frozen, scroll)))
+        .collect();
+    f.render_widget(
+        Paragraph::new(header).style(config.text_style()),
+        header_area,
+    );
+
+    // only the visible lines go through the viewport
+    let offset = tui.scroll_offset();
+    let cell_width = tui
+        .column_starts
+        .iter()
+        .find(|&&s| s > offset)
+        .map_or(usize::MAX, |end| end - offset - 1);
+    let text: Vec<Line> = body
+        .iter()
+        .enumerate()
+        .skip(tui.vertical_scroll as usize)
+        .take(body_area.height as usize)
+        .map(|(i, line)| {
+            let visible = viewport(line, frozen, scroll);
+            match &tui.pattern {
+                _ if i == tui.cursor_row => cursor_line(visible, frozen, cell_width),
+                Some(pattern) if cell_match(line, pattern) => highlight(visible, pattern),
+                _ => Line::from(visible),
+            }
+        })
+        .collect();
+    f.render_widget(Paragraph::new(text).style(config.text_style()), body_area);
+
+    let status = match (&tui.search_input, &tui.pattern) {
+        (Some(input), _) => format!("/{}", input),
+        _ if tui.export_prompt.is_some() => match &tui.export_prompt {
+            Some(ExportPrompt::Path(path)) => format!("export to: {}", path),
+            Some(ExportPrompt::Format { input, .. }) => {
+                format!("format (csv, json or parquet): {}", input)
+            }
+            None => String::new(),
+        },
+        (None, _) if tui.message.is_some() => tui.message.clone().unwrap_or_default(),
+        (None, Some(pattern)) if tui.fetch_prompt => format!(
+            "search: {} (no more match in fetched rows, n to fetch more)",
+            pattern
+        ),
+        (None, Some(pattern)) => format!("search: {} (n/N next/previous match, q quit)", pattern),
+        (None, None) => String::from(
+            "q quit, / search, PgUp/PgDn page, g/G top/bottom, h/l column, f/F freeze/unfreeze, y copy, e export",
+        ),
+    };
+    let status = if exhausted {
+        status
+    } else {
+        format!("{} (more rows fetched on scroll)", status)
+    };
+    f.render_widget(
+        Paragraph::new(status).style(config.status_style()),
+        status_area,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
 
-    let paragraph = Paragraph::new(text)
-        .gray()
-        .scroll((tui.vertical_scroll as u16, tui.horizontal_scroll as u16));
-    f.render_widget(paragraph, area);
+    #[test]
+    fn shift_letter_matches_upper_case() {
+        let binding: KeyBinding = "shift+j".parse().unwrap();
+        assert_eq!(binding, KeyBinding::char('J'));
+        assert!(binding.matches(&key(KeyCode::Char('J'), KeyModifiers::NONE)));
+        assert!(binding.matches(&key(KeyCode::Char('J'), KeyModifiers::SHIFT)));
+        assert!(!binding.matches(&key(KeyCode::Char('j'), KeyModifiers::NONE)));
+    }
+
+    #[test]
+    fn default_fast_keys() {
+        let config = TuiConfig::default();
+        let shift_j = key(KeyCode::Char('J'), KeyModifiers::SHIFT);
+        assert_eq!(config.action(&shift_j), Some(Action::FastDown));
+        let shift_down = key(KeyCode::Down, KeyModifiers::SHIFT);
+        assert_eq!(config.action(&shift_down), Some(Action::FastDown));
+        assert!(config.check_keys().is_ok());
+    }
+
+    #[test]
+    fn duplicate_keys_are_rejected() {
+        let mut config = TuiConfig::default();
+        config
+            .keys
+            .insert(Action::Copy, vec!["shift+n".parse().unwrap()]);
+        assert!(config.check_keys().is_err());
+    }
+
+    #[test]
+    fn column_starts_from_border() {
+        assert_eq!(column_starts("+----+------+---+"), vec![0, 6, 13]);
+        assert_eq!(column_starts("+---+"), vec![0]);
+        assert_eq!(column_starts(""), vec![0]);
+    }
+
+    #[test]
+    fn viewport_keeps_frozen_chars() {
+        let line = "| id | name  | city   |";
+        assert_eq!(viewport(line, 0, 0), line);
+        assert_eq!(viewport(line, 0, 5), "| name  | city   |");
+        assert_eq!(viewport(line, 5, 8), "| id | city   |");
+        assert_eq!(viewport(line, 5, 100), "| id ");
+    }
 }
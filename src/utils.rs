@@ -1,6 +1,29 @@
-use datafusion::arrow::datatypes::{DataType, TimeUnit};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use base64::prelude::*;
+use datafusion::arrow::array::{Array, ArrayRef, AsArray, LargeStringArray, StringArray};
+use datafusion::arrow::csv;
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use datafusion::arrow::error::ArrowError;
+use datafusion::arrow::json::{ArrayWriter, LineDelimitedWriter};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::arrow::util::display::{ArrayFormatter, FormatOptions};
+use datafusion::arrow::util::pretty::pretty_format_batches;
+use datafusion::datasource::file_format::file_compression_type::FileCompressionType;
+use datafusion::error::DataFusionError;
+use deltalake::DeltaTableError;
+use object_store::aws::{AmazonS3Builder, AmazonS3ConfigKey};
+use object_store::gcp::{GoogleCloudStorageBuilder, GoogleConfigKey};
+use serde_json::json;
 use url::{ParseError, Url};
 
+use crate::cli::{BinaryDisplay, PrintFormat};
+
 pub fn type_from_str(type_str: &str) -> Result<DataType, String> {
     match type_str {
         "int" => Ok(DataType::Int32),
@@ -15,11 +38,280 @@ pub fn type_from_str(type_str: &str) -> Result<DataType, String> {
     }
 }
 
+/// Parse a comma separated `name:type` partition spec, e.g. `year:int,day:date`
+///
+/// Errors point at the offending entry.
+pub fn parse_partition_spec(spec: &str) -> Result<Vec<(String, DataType)>, String> {
+    spec.split(',')
+        .map(|entry| entry.trim())
+        .enumerate()
+        .map(|(i, entry)| {
+            let (name, type_str) = entry.split_once(':').ok_or_else(|| {
+                format!("partition #{} {:?} is not a name:type pair", i + 1, entry)
+            })?;
+            let (name, type_str) = (name.trim(), type_str.trim());
+            if name.is_empty() || name.chars().any(|c| c.is_whitespace() || c == ':') {
+                return Err(format!("invalid partition name {:?} in {:?}", name, entry));
+            }
+            let data_type = type_from_str(type_str).map_err(|_| {
+                format!(
+                    "unsupported partition type {:?} in {:?}, expected int, bigint, float, double, string, date, timestamp or timestamp_ms",
+                    type_str, entry
+                )
+            })?;
+            Ok((name.to_string(), data_type))
+        })
+        .collect()
+}
+
+/// Render nested (struct, list, map) columns as strings expanded up to `max_depth` levels,
+/// deeper values being summarized as `{...}` or `[...]`
+pub fn limit_nested_depth(
+    records: &[RecordBatch],
+    max_depth: usize,
+) -> Result<Vec<RecordBatch>, ArrowError> {
+    records
+        .iter()
+        .map(|batch| {
+            let mut fields = Vec::with_capacity(batch.num_columns());
+            let mut columns = Vec::with_capacity(batch.num_columns());
+            for (field, column) in batch.schema().fields().iter().zip(batch.columns()) {
+                if is_nested(field.data_type()) {
+                    let rendered = (0..column.len())
+                        .map(|row| {
+                            if column.is_null(row) {
+                                Ok(None)
+                            } else {
+                                render_nested(column.as_ref(), row, max_depth).map(Some)
+                            }
+                        })
+                        .collect::<Result<StringArray, ArrowError>>()?;
+                    fields.push(Field::new(field.name(), DataType::Utf8, true));
+                    columns.push(Arc::new(rendered) as ArrayRef);
+                } else {
+                    fields.push(field.as_ref().clone());
+                    columns.push(column.clone());
+                }
+            }
+            RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)
+        })
+        .collect()
+}
+
+fn is_nested(data_type: &DataType) -> bool {
+    matches!(
+        data_type,
+        DataType::Struct(_) | DataType::List(_) | DataType::LargeList(_) | DataType::Map(_, _)
+    )
+}
+
+fn render_nested(array: &dyn Array, row: usize, depth: usize) -> Result<String, ArrowError> {
+    if array.is_null(row) {
+        return Ok(String::from("null"));
+    }
+    let rendered = match array.data_type() {
+        DataType::Struct(fields) if depth == 0 && !fields.is_empty() => String::from("{...}"),
+        DataType::Struct(fields) => {
+            let values = array
+                .as_struct()
+                .columns()
+                .iter()
+                .zip(fields.iter())
+                .map(|(child, f)| {
+                    Ok(format!(
+                        "{}: {}",
+                        f.name(),
+                        render_nested(child, row, depth - 1)?
+                    ))
+                })
+                .collect::<Result<Vec<_>, ArrowError>>()?;
+            format!("{{{}}}", values.join(", "))
+        }
+        DataType::List(_) | DataType::LargeList(_) if depth == 0 => String::from("[...]"),
+        DataType::List(_) => render_list(array.as_list::<i32>().value(row).as_ref(), depth)?,
+        DataType::LargeList(_) => render_list(array.as_list::<i64>().value(row).as_ref(), depth)?,
+        DataType::Map(_, _) if depth == 0 => String::from("{...}"),
+        DataType::Map(_, _) => {
+            let entries = array.as_map().value(row);
+            let values = (0..entries.len())
+                .map(|i| {
+                    Ok(format!(
+                        "{}: {}",
+                        render_nested(entries.column(0).as_ref(), i, depth - 1)?,
+                        render_nested(entries.column(1).as_ref(), i, depth - 1)?
+                    ))
+                })
+                .collect::<Result<Vec<_>, ArrowError>>()?;
+            format!("{{{}}}", values.join(", "))
+        }
+        _ => ArrayFormatter::try_new(array, &FormatOptions::default())?
+            .value(row)
+            .to_string(),
+    };
+    Ok(rendered)
+}
+
+fn render_list(values: &dyn Array, depth: usize) -> Result<String, ArrowError> {
+    let items = (0..values.len())
+        .map(|i| render_nested(values, i, depth - 1))
+        .collect::<Result<Vec<_>, ArrowError>>()?;
+    Ok(format!("[{}]", items.join(", ")))
+}
+
+/// Query cancelled before completion, by `--timeout-secs` or Ctrl-C
+#[derive(Debug)]
+pub enum Cancelled {
+    /// timed out after the given number of seconds
+    TimedOut(u64),
+    Interrupted,
+}
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Cancelled::TimedOut(secs) => write!(f, "query timed out after {}s", secs),
+            Cancelled::Interrupted => write!(f, "query interrupted"),
+        }
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Kind of the innermost typed error of `err`: `cancelled`, `io`, `arrow`, `object_store`,
+/// `delta` or `datafusion`, `usage` for errors raised by adt itself (invalid options, paths...)
+pub fn error_type(err: &anyhow::Error) -> &'static str {
+    err.chain()
+        .rev()
+        .find_map(|cause| {
+            if cause.is::<Cancelled>() {
+                Some("cancelled")
+            } else if cause.is::<std::io::Error>() {
+                Some("io")
+            } else if cause.is::<ArrowError>() {
+                Some("arrow")
+            } else if cause.is::<object_store::Error>() {
+                Some("object_store")
+            } else if cause.is::<DeltaTableError>() {
+                Some("delta")
+            } else if cause.is::<DataFusionError>() {
+                Some("datafusion")
+            } else {
+                None
+            }
+        })
+        .unwrap_or("usage")
+}
+
+/// Render a command error as a `{error_type, message, context}` json object
+///
+/// `context` is the outermost context attached to the error, `message` its root cause.
+pub fn json_error(err: &anyhow::Error) -> String {
+    let message = err.root_cause().to_string();
+    let context = if err.chain().count() > 1 {
+        err.to_string()
+    } else {
+        String::new()
+    };
+    json!({"error_type": error_type(err), "message": message, "context": context}).to_string()
+}
+
+/// Panic hook printing unexpected panics in the same json format as `json_error` on stderr
+///
+/// `expect("<context>")` panic messages are split into the expectation (context) and the
+/// underlying error (message).
+pub fn json_panic_hook(info: &std::panic::PanicHookInfo) {
+    let payload = info
+        .payload()
+        .downcast_ref::<String>()
+        .map(|s| s.as_str())
+        .or_else(|| info.payload().downcast_ref::<&str>().copied())
+        .unwrap_or("unknown error");
+    let (context, message) = payload.split_once(": ").unwrap_or(("", payload));
+    eprintln!(
+        "{}",
+        json!({"error_type": "panic", "message": message, "context": context})
+    );
+}
+
+/// Render a byte count with a binary unit, e.g. `1.50 MiB`
+pub fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.2} {}", value, UNITS[unit])
+    }
+}
+
+/// Infer the compression of a file from its extension, e.g. `events.ndjson.gz`
+pub fn compression_from_path(path: &str) -> FileCompressionType {
+    match path.rsplit('.').next() {
+        Some("gz") => FileCompressionType::GZIP,
+        Some("bz2") => FileCompressionType::BZIP2,
+        Some("xz") => FileCompressionType::XZ,
+        Some("zst") => FileCompressionType::ZSTD,
+        _ => FileCompressionType::UNCOMPRESSED,
+    }
+}
+
+/// S3 store builder for the bucket of `url`, configured from the environment then `options`
+///
+/// Options use the object store config names (`endpoint`, `region`, `allow_http`,
+/// `access_key_id`, `secret_access_key`...), unknown ones are ignored. An `http://`
+/// endpoint allows plain http.
+pub fn s3_builder(url: &Url, options: &HashMap<String, String>) -> Result<AmazonS3Builder> {
+    let mut builder = AmazonS3Builder::from_env().with_bucket_name(
+        url.host_str()
+            .context("failed to extract host/bucket from path")?,
+    );
+    for (key, value) in options {
+        if let Ok(key) = AmazonS3ConfigKey::from_str(key) {
+            if key == AmazonS3ConfigKey::Endpoint && value.starts_with("http://") {
+                builder = builder.with_allow_http(true);
+            }
+            builder = builder.with_config(key, value);
+        }
+    }
+    Ok(builder)
+}
+
+/// GCS store builder for the bucket of `url`, configured from the environment then `options`
+///
+/// Options use the object store config names (`google_application_credentials`,
+/// `google_service_account_key`...), unknown ones are ignored.
+pub fn gcs_builder(
+    url: &Url,
+    options: &HashMap<String, String>,
+) -> Result<GoogleCloudStorageBuilder> {
+    let mut builder = GoogleCloudStorageBuilder::from_env().with_bucket_name(
+        url.host_str()
+            .context("failed to extract host/bucket from path")?,
+    );
+    for (key, value) in options {
+        if let Ok(key) = GoogleConfigKey::from_str(key) {
+            builder = builder.with_config(key, value);
+        }
+    }
+    Ok(builder)
+}
+
+/// Error raised when an azure store can't be built, usually because of missing credentials
+pub const AZURE_STORE_ERROR: &str =
+    "Unable to create Azure object store, set AZURE_STORAGE_ACCOUNT_NAME \
+     and AZURE_STORAGE_ACCOUNT_KEY (or AZURE_STORAGE_CLIENT_ID, AZURE_STORAGE_CLIENT_SECRET and \
+     AZURE_STORAGE_TENANT_ID)";
+
 pub fn ensure_scheme(s: &str) -> Result<Url, ()> {
     match Url::parse(s) {
         Ok(url) => Ok(url),
         Err(ParseError::RelativeUrlWithoutBase) => {
-            let local_path = std::path::Path::new(s).canonicalize().unwrap();
+            let local_path = std::path::Path::new(s).canonicalize().map_err(|_| ())?;
             if local_path.is_file() {
                 Url::from_file_path(&local_path)
             } else {
@@ -29,3 +321,248 @@ pub fn ensure_scheme(s: &str) -> Result<Url, ()> {
         Err(_) => Err(()),
     }
 }
+
+/// Insert a `...` line in a pretty formatted table after its first `rows` rows
+pub fn with_row_separator(table: &str, rows: usize) -> String {
+    let mut lines: Vec<&str> = table.lines().collect();
+    // rows start after the top border, column names and header separator lines
+    let at = (3 + rows).min(lines.len());
+    lines.insert(at, "...");
+    lines.join("\n")
+}
+
+/// Truncate displayed string values longer than `max_width` chars with an ellipsis
+pub fn truncate_strings(
+    records: &[RecordBatch],
+    max_width: usize,
+) -> Result<Vec<RecordBatch>, ArrowError> {
+    let truncate = |value: &str| match value.char_indices().nth(max_width.saturating_sub(1)) {
+        Some((end, _)) if value.chars().count() > max_width => format!("{}…", &value[..end]),
+        _ => value.to_string(),
+    };
+    records
+        .iter()
+        .map(|batch| {
+            let columns = batch
+                .columns()
+                .iter()
+                .map(|column| match column.data_type() {
+                    DataType::Utf8 => Arc::new(
+                        column
+                            .as_string::<i32>()
+                            .iter()
+                            .map(|v| v.map(truncate))
+                            .collect::<StringArray>(),
+                    ) as ArrayRef,
+                    DataType::LargeUtf8 => Arc::new(
+                        column
+                            .as_string::<i64>()
+                            .iter()
+                            .map(|v| v.map(truncate))
+                            .collect::<LargeStringArray>(),
+                    ) as ArrayRef,
+                    _ => column.clone(),
+                })
+                .collect();
+            RecordBatch::try_new(batch.schema(), columns)
+        })
+        .collect()
+}
+
+/// Bytes shown by `BinaryDisplay::Truncate`
+const TRUNCATED_BINARY_BYTES: usize = 8;
+
+/// Render binary columns as strings, their raw bytes garbling the terminal otherwise
+pub fn display_binary(
+    records: &[RecordBatch],
+    mode: BinaryDisplay,
+) -> Result<Vec<RecordBatch>, ArrowError> {
+    records
+        .iter()
+        .map(|batch| {
+            let mut fields = Vec::with_capacity(batch.num_columns());
+            let mut columns = Vec::with_capacity(batch.num_columns());
+            for (field, column) in batch.schema().fields().iter().zip(batch.columns()) {
+                let values: Vec<Option<&[u8]>> = match field.data_type() {
+                    DataType::Binary => column.as_binary::<i32>().iter().collect(),
+                    DataType::LargeBinary => column.as_binary::<i64>().iter().collect(),
+                    DataType::FixedSizeBinary(_) => column.as_fixed_size_binary().iter().collect(),
+                    _ => {
+                        fields.push(field.as_ref().clone());
+                        columns.push(column.clone());
+                        continue;
+                    }
+                };
+                let rendered: StringArray = values
+                    .into_iter()
+                    .map(|v| v.map(|bytes| render_binary(bytes, mode)))
+                    .collect();
+                fields.push(Field::new(
+                    field.name(),
+                    DataType::Utf8,
+                    field.is_nullable(),
+                ));
+                columns.push(Arc::new(rendered) as ArrayRef);
+            }
+            RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)
+        })
+        .collect()
+}
+
+fn render_binary(bytes: &[u8], mode: BinaryDisplay) -> String {
+    let hex = |bytes: &[u8]| {
+        bytes
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    };
+    match mode {
+        BinaryDisplay::Hex => hex(bytes),
+        BinaryDisplay::Base64 => BASE64_STANDARD.encode(bytes),
+        BinaryDisplay::Truncate if bytes.len() > TRUNCATED_BINARY_BYTES => format!(
+            "{}... ({} bytes)",
+            hex(&bytes[..TRUNCATED_BINARY_BYTES]),
+            bytes.len()
+        ),
+        BinaryDisplay::Truncate => hex(bytes),
+    }
+}
+
+/// Rename each column to `name (type)` so the displayed header shows arrow types
+pub fn with_types_in_header(
+    schema: SchemaRef,
+    records: &[RecordBatch],
+) -> Result<Vec<RecordBatch>, ArrowError> {
+    let fields: Vec<Field> = schema
+        .fields()
+        .iter()
+        .map(|f| {
+            f.as_ref()
+                .clone()
+                .with_name(format!("{} ({})", f.name(), f.data_type()))
+        })
+        .collect();
+    let typed_schema = Arc::new(Schema::new(fields));
+    if records.is_empty() {
+        return Ok(vec![RecordBatch::new_empty(typed_schema)]);
+    }
+    records
+        .iter()
+        .map(|batch| RecordBatch::try_new(typed_schema.clone(), batch.columns().to_vec()))
+        .collect()
+}
+
+/// Write `records` to `writer` as a pretty table, newline delimited json or csv
+///
+/// Nested columns are written as json objects and arrays, csv having no nested types
+/// fails on them.
+pub fn write_records<W: Write>(
+    records: &[RecordBatch],
+    fmt: PrintFormat,
+    mut writer: W,
+) -> Result<(), ArrowError> {
+    match fmt {
+        PrintFormat::Table => writeln!(writer, "{}", pretty_format_batches(records)?)?,
+        PrintFormat::Json => {
+            let mut json = LineDelimitedWriter::new(writer);
+            json.write_batches(&records.iter().collect::<Vec<_>>())?;
+            json.finish()?;
+        }
+        PrintFormat::Csv => {
+            let mut csv = csv::Writer::new(writer);
+            for batch in records {
+                csv.write(batch)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write `records` to `writer` as a single json array of row objects
+pub fn write_json_array<W: Write>(records: &[RecordBatch], writer: W) -> Result<(), ArrowError> {
+    let mut json = ArrayWriter::new(writer);
+    json.write_batches(&records.iter().collect::<Vec<_>>())?;
+    json.finish()
+}
+
+/// Versions of adt and its key dependencies, captured at build time, as a json object
+pub fn version_info() -> String {
+    json!({
+        "adt": env!("CARGO_PKG_VERSION"),
+        "arrow": env!("ADT_DEP_VERSION_ARROW"),
+        "datafusion": env!("ADT_DEP_VERSION_DATAFUSION"),
+        "deltalake": env!("ADT_DEP_VERSION_DELTALAKE"),
+        "deltalake-core": env!("ADT_DEP_VERSION_DELTALAKE_CORE"),
+        "object_store": env!("ADT_DEP_VERSION_OBJECT_STORE"),
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::Int32Array;
+
+    fn records() -> Vec<RecordBatch> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            Field::new("name", DataType::Utf8, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(Int32Array::from(vec![1, 2])),
+                Arc::new(StringArray::from(vec![Some("a"), None])),
+            ],
+        )
+        .unwrap();
+        vec![batch]
+    }
+
+    #[test]
+    fn json_lines() {
+        let mut out = vec![];
+        write_records(&records(), PrintFormat::Json, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"id\":1,\"name\":\"a\"}\n{\"id\":2}\n"
+        );
+    }
+
+    #[test]
+    fn json_array() {
+        let mut out = vec![];
+        write_json_array(&records(), &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "[{\"id\":1,\"name\":\"a\"},{\"id\":2}]"
+        );
+    }
+
+    #[test]
+    fn good_partition_specs() {
+        assert_eq!(
+            parse_partition_spec("year:int, day : date").unwrap(),
+            vec![
+                ("year".to_string(), DataType::Int32),
+                ("day".to_string(), DataType::Date32)
+            ]
+        );
+        assert_eq!(parse_partition_spec("country:string").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn bad_partition_specs() {
+        let err = parse_partition_spec("year int").unwrap_err();
+        assert!(err.contains("not a name:type pair"), "{}", err);
+        let err = parse_partition_spec("year:int,:date").unwrap_err();
+        assert!(err.contains("invalid partition name"), "{}", err);
+        let err = parse_partition_spec("year:integer").unwrap_err();
+        assert!(
+            err.contains("unsupported partition type \"integer\""),
+            "{}",
+            err
+        );
+        assert!(parse_partition_spec("year:int,").is_err());
+    }
+}
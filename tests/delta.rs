@@ -0,0 +1,138 @@
+use adt::cli::Format;
+use adt::context::SQLContext;
+use adt::query;
+use adt::table::TableContext;
+use datafusion::arrow::array::Int64Array;
+use datafusion::arrow::util::pretty::pretty_format_batches;
+use datafusion::physical_plan::collect;
+use std::sync::Arc;
+
+const EMPTY_DELTA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/empty_delta");
+
+#[tokio::test(flavor = "multi_thread")]
+async fn empty_table() {
+    let batches = query(EMPTY_DELTA, Format::Delta, "select count(*) from tbl")
+        .await
+        .unwrap();
+    let count = batches[0]
+        .column(0)
+        .as_any()
+        .downcast_ref::<Int64Array>()
+        .unwrap();
+    assert_eq!(count.value(0), 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn empty_external_table() {
+    let ctx = SQLContext::new().unwrap();
+    ctx.sql(&format!(
+        "create external table tbl stored as delta location '{}'",
+        EMPTY_DELTA
+    ))
+    .await
+    .unwrap();
+    let batches = ctx
+        .sql("select * from tbl")
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+    assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 0);
+}
+
+const COLUMN_MAPPING_DELTA: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/data/column_mapping_delta"
+);
+
+const COLUMN_MAPPING_ROWS: &str = "\
++----+-------+---------+
+| id | name  | country |
++----+-------+---------+
+| 1  | alice | fr      |
+| 2  | bob   | fr      |
+| 3  | carol | us      |
++----+-------+---------+";
+
+#[tokio::test(flavor = "multi_thread")]
+async fn column_mapping() {
+    let batches = query(
+        COLUMN_MAPPING_DELTA,
+        Format::Delta,
+        "select id, name, country from tbl order by id",
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        pretty_format_batches(&batches).unwrap().to_string(),
+        COLUMN_MAPPING_ROWS
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn column_mapping_projection() {
+    let batches = query(
+        COLUMN_MAPPING_DELTA,
+        Format::Delta,
+        "select country, sum(id) as total from tbl group by country order by country",
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        pretty_format_batches(&batches).unwrap().to_string(),
+        "\
++---------+-------+
+| country | total |
++---------+-------+
+| fr      | 3     |
+| us      | 3     |
++---------+-------+"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn column_mapping_external_table() {
+    let ctx = SQLContext::new().unwrap();
+    ctx.sql(&format!(
+        "create external table tbl stored as delta location '{}'",
+        COLUMN_MAPPING_DELTA
+    ))
+    .await
+    .unwrap();
+    let batches = ctx
+        .sql("select id, name, country from tbl order by id")
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+    assert_eq!(
+        pretty_format_batches(&batches).unwrap().to_string(),
+        COLUMN_MAPPING_ROWS
+    );
+}
+
+const PRUNING_DELTA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/pruning_delta");
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pruning_summary() {
+    // three files whose amount ranges are 10-20, 50-60 and 150-200
+    let tblctx = TableContext::new(PRUNING_DELTA, &None, Format::Delta).unwrap();
+    tblctx.register_table().await.unwrap();
+    let df = tblctx
+        .context()
+        .sql("select id from tbl where amount > 100 order by id")
+        .await
+        .unwrap();
+    let task_ctx = Arc::new(df.task_ctx());
+    let plan = df.create_physical_plan().await.unwrap();
+    let batches = collect(plan.clone(), task_ctx).await.unwrap();
+    assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 2);
+    let summary = tblctx.pruning_summary(&plan);
+    assert!(
+        summary.starts_with("files: 1 scanned / 3 active (2 pruned)\n"),
+        "{}",
+        summary
+    );
+}
@@ -0,0 +1,21 @@
+use std::process::Command;
+
+use serde_json::Value;
+
+#[test]
+fn json_error_on_missing_file() {
+    let output = Command::new(env!("CARGO_BIN_EXE_adt"))
+        .args(["--log-level", "off", "--error-format", "json"])
+        .args(["view", "--no-tui", "does/not/exist.parquet"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let error: Value = serde_json::from_str(stderr.trim().lines().last().unwrap()).unwrap();
+    assert_eq!(error["error_type"], "io");
+    assert!(!error["message"].as_str().unwrap().is_empty());
+    assert!(error["context"]
+        .as_str()
+        .unwrap()
+        .contains("invalid table path does/not/exist.parquet"));
+}
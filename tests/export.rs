@@ -0,0 +1,43 @@
+use std::process::Command;
+
+use adt::cli::Format;
+use adt::query;
+use datafusion::arrow::array::{Int64Array, StringArray};
+
+fn fixture(name: &str) -> String {
+    format!("{}/tests/data/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn parquet_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("people.parquet");
+    let status = Command::new(env!("CARGO_BIN_EXE_adt"))
+        .args(["--log-level", "off", "view", "--no-tui"])
+        .args(["--parquet-compression", "zstd", "--output-path"])
+        .arg(&output)
+        .arg(fixture("people.csv.gz"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let batches = query(
+        output.to_str().unwrap(),
+        Format::Parquet,
+        "select id, name from tbl order by id",
+    )
+    .await
+    .unwrap();
+    let ids = batches[0]
+        .column(0)
+        .as_any()
+        .downcast_ref::<Int64Array>()
+        .unwrap();
+    let names = batches[0]
+        .column(1)
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .unwrap();
+    assert_eq!((ids.value(0), ids.value(1)), (1, 2));
+    assert_eq!(names.value(0), "alice");
+    assert_eq!(names.value(1), "bob");
+}
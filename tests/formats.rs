@@ -0,0 +1,163 @@
+use adt::cli::Format;
+use adt::query;
+use adt::table::TableContext;
+use datafusion::arrow::array::Int64Array;
+use datafusion::arrow::record_batch::RecordBatch;
+
+fn fixture(name: &str) -> String {
+    format!("{}/tests/data/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+/// Single int64 value of a one row result, e.g. `select count(*)`
+fn scalar(batches: &[RecordBatch]) -> i64 {
+    batches[0]
+        .column(0)
+        .as_any()
+        .downcast_ref::<Int64Array>()
+        .unwrap()
+        .value(0)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn gzip_ndjson() {
+    let batches = query(
+        &fixture("events.ndjson.gz"),
+        Format::Json,
+        "select count(*) from tbl where event = 'click'",
+    )
+    .await
+    .unwrap();
+    assert_eq!(scalar(&batches), 2);
+}
+
+#[test]
+fn json_lines_extensions() {
+    for path in [
+        "events.json",
+        "events.ndjson",
+        "events.jsonl",
+        "events.jsonl.gz",
+    ] {
+        assert_eq!(Format::from_extension(path), Some(Format::Json), "{}", path);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn inferred_ndjson() {
+    let batches = query(
+        &fixture("events.ndjson.gz"),
+        Format::Auto,
+        "select count(*) from tbl where event = 'click'",
+    )
+    .await
+    .unwrap();
+    assert_eq!(scalar(&batches), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn inferred_jsonl() {
+    let batches = query(
+        &fixture("events.jsonl"),
+        Format::Auto,
+        "select count(*) from tbl where event = 'view'",
+    )
+    .await
+    .unwrap();
+    assert_eq!(scalar(&batches), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn gzip_csv() {
+    let batches = query(
+        &fixture("people.csv.gz"),
+        Format::Csv,
+        "select max(id) from tbl",
+    )
+    .await
+    .unwrap();
+    assert_eq!(scalar(&batches), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn zstd_json() {
+    // the format is inferred through the compression suffix
+    let batches = query(
+        &fixture("sales.json.zst"),
+        Format::Auto,
+        "select sum(amount) from tbl",
+    )
+    .await
+    .unwrap();
+    assert_eq!(scalar(&batches), 42);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn semicolon_headerless_csv() {
+    let tblctx = TableContext::new(&fixture("semicolon.csv"), &None, Format::Csv)
+        .unwrap()
+        .with_csv_options(Some(';'), None, None, false)
+        .unwrap();
+    tblctx.register_table().await.unwrap();
+    let batches = tblctx
+        .context()
+        .sql("select sum(column_3) from tbl where column_2 = 'bob'")
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+    assert_eq!(scalar(&batches), 4);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn latin1_csv() {
+    let tblctx = TableContext::new(&fixture("latin1.csv"), &None, Format::Auto)
+        .unwrap()
+        .with_encoding(&Some("latin1".to_string()))
+        .unwrap();
+    tblctx.register_table().await.unwrap();
+    let batches = tblctx
+        .context()
+        .sql("select count(*) from tbl where name in ('José', 'Zoë')")
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+    assert_eq!(scalar(&batches), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn encoding_rejects_other_formats() {
+    let err = TableContext::new(&fixture("users.avro"), &None, Format::Auto)
+        .unwrap()
+        .with_encoding(&Some("latin1".to_string()))
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("csv and json"), "{}", err);
+    let err = TableContext::new(&fixture("people.csv.gz"), &None, Format::Csv)
+        .unwrap()
+        .with_encoding(&Some("latin1".to_string()))
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("uncompressed"), "{}", err);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn avro_schema() {
+    let tblctx = TableContext::new(&fixture("users.avro"), &None, Format::Auto).unwrap();
+    tblctx.register_table().await.unwrap();
+    assert_eq!(tblctx.format().await.unwrap(), Format::Avro);
+    assert_eq!(tblctx.column_names().await.unwrap(), vec!["id", "name"]);
+    let columns = tblctx.schema().await.unwrap().collect().await.unwrap();
+    assert_eq!(columns.iter().map(|b| b.num_rows()).sum::<usize>(), 2);
+    let batches = tblctx
+        .context()
+        .sql("select sum(id) from tbl")
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+    assert_eq!(scalar(&batches), 6);
+}
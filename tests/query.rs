@@ -0,0 +1,101 @@
+use adt::cli::Format;
+use adt::table::{QueryOptions, TableContext};
+use datafusion::arrow::array::Int64Array;
+use datafusion::arrow::record_batch::RecordBatch;
+
+const SCORES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/scores.csv");
+
+async fn scores() -> TableContext {
+    let tblctx = TableContext::new(SCORES, &None, Format::Csv).unwrap();
+    tblctx.register_table().await.unwrap();
+    tblctx
+}
+
+/// Values of the int64 `column` of the result, in order
+fn int64s(batches: &[RecordBatch], column: &str) -> Vec<i64> {
+    batches
+        .iter()
+        .flat_map(|batch| {
+            let array = batch
+                .column_by_name(column)
+                .unwrap()
+                .as_any()
+                .downcast_ref::<Int64Array>()
+                .unwrap();
+            array.values().to_vec()
+        })
+        .collect()
+}
+
+async fn ordered(options: QueryOptions) -> anyhow::Result<Vec<RecordBatch>> {
+    let df = scores()
+        .await
+        .exec_query("select * from tbl".to_string(), 0, &options)
+        .await?;
+    Ok(df.collect().await?)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn order_by_desc() {
+    let batches = ordered(QueryOptions {
+        order_by: Some("id:desc".to_string()),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    assert_eq!(int64s(&batches, "id"), [4, 3, 2, 1]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn order_by_multiple_keys() {
+    let batches = ordered(QueryOptions {
+        order_by: Some("score:desc, id:asc".to_string()),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    assert_eq!(int64s(&batches, "id"), [2, 4, 3, 1]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn order_by_rename_target() {
+    let batches = ordered(QueryOptions {
+        order_by: Some("points:desc,ident:desc".to_string()),
+        renames: vec![
+            ("score".to_string(), "points".to_string()),
+            ("id".to_string(), "ident".to_string()),
+        ],
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    assert_eq!(int64s(&batches, "ident"), [4, 2, 3, 1]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn order_by_aggregate_alias() {
+    let batches = ordered(QueryOptions {
+        group_by: vec!["team".to_string()],
+        aggregates: vec!["sum(score) as total".to_string()],
+        order_by: Some("total".to_string()),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    assert_eq!(int64s(&batches, "total"), [30, 60]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn order_by_missing_column() {
+    let err = ordered(QueryOptions {
+        order_by: Some("id,nope:desc".to_string()),
+        ..Default::default()
+    })
+    .await
+    .unwrap_err();
+    assert!(
+        err.to_string().contains("order by column nope not found"),
+        "{}",
+        err
+    );
+}